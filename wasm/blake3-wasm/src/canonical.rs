@@ -0,0 +1,164 @@
+use std::borrow::Cow;
+
+/// The bytes of `buffer` with its metadata removed, as slices to feed to a
+/// hasher in order. Joined, they form a valid file of the same format.
+///
+/// - JPEG: every APPn segment and COM before the first scan is dropped,
+///   except an Adobe APP14 (it selects the color transform) and APP2
+///   ICC_PROFILE segments.
+/// - PNG: only critical chunks (IHDR, PLTE, IDAT, IEND) and the ones that
+///   change how pixels render (tRNS, gAMA, cHRM, sRGB, iCCP, sBIT, and the
+///   APNG acTL, fcTL and fdAT) are kept.
+/// - WebP: EXIF and XMP chunks are dropped and their VP8X flags cleared.
+///
+/// Pixel data is never decoded or re-encoded. Other formats are an error.
+pub fn canonical_parts(buffer: &[u8]) -> Result<Vec<Cow<'_, [u8]>>, String> {
+    if buffer.starts_with(&[0xFF, 0xD8]) {
+        jpeg_parts(buffer)
+    } else if buffer.starts_with(PNG_SIGNATURE) {
+        png_parts(buffer)
+    } else if buffer.get(..4) == Some(&b"RIFF"[..]) && buffer.get(8..12) == Some(&b"WEBP"[..]) {
+        webp_parts(buffer)
+    } else {
+        Err("Unsupported format: canonical hashing needs JPEG, PNG or WebP".to_string())
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Ancillary PNG chunks that affect how the pixels look. The APNG ones carry
+/// every frame after the first, so dropping them would collide animations
+/// that share a first frame.
+const PNG_RENDERING_CHUNKS: [&[u8; 4]; 9] = [
+    b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"acTL", b"fcTL", b"fdAT",
+];
+
+fn jpeg_parts(jpeg: &[u8]) -> Result<Vec<Cow<'_, [u8]>>, String> {
+    let mut parts = vec![Cow::Borrowed(&jpeg[..2])];
+    let mut pos = 2;
+    loop {
+        let Some(&[0xFF, marker, hi, lo]) = jpeg.get(pos..pos + 4) else {
+            return Err("Malformed JPEG header".to_string());
+        };
+        // Start of scan: entropy-coded data follows and is kept as is.
+        if marker == 0xDA {
+            parts.push(Cow::Borrowed(&jpeg[pos..]));
+            return Ok(parts);
+        }
+        let end = pos + 2 + u16::from_be_bytes([hi, lo]) as usize;
+        let payload = jpeg
+            .get(pos + 4..end)
+            .ok_or_else(|| "Truncated JPEG segment".to_string())?;
+        let metadata = match marker {
+            0xE2 => !payload.starts_with(b"ICC_PROFILE\0"),
+            0xEE => !payload.starts_with(b"Adobe"),
+            0xE0..=0xEF | 0xFE => true,
+            _ => false,
+        };
+        if !metadata {
+            parts.push(Cow::Borrowed(&jpeg[pos..end]));
+        }
+        pos = end;
+    }
+}
+
+fn png_parts(png: &[u8]) -> Result<Vec<Cow<'_, [u8]>>, String> {
+    let mut parts = vec![Cow::Borrowed(PNG_SIGNATURE)];
+    let mut pos = PNG_SIGNATURE.len();
+    while let Some(header) = png.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let end = pos + 12 + len;
+        let chunk = png
+            .get(pos..end)
+            .ok_or_else(|| "Truncated PNG chunk".to_string())?;
+        // Critical chunks have an uppercase first letter.
+        if kind[0].is_ascii_uppercase() || PNG_RENDERING_CHUNKS.contains(&&kind) {
+            parts.push(Cow::Borrowed(chunk));
+        }
+        if &kind == b"IEND" {
+            return Ok(parts);
+        }
+        pos = end;
+    }
+    Err("PNG has no IEND chunk".to_string())
+}
+
+fn webp_parts(webp: &[u8]) -> Result<Vec<Cow<'_, [u8]>>, String> {
+    // Chunks after the 12-byte RIFF header, padding included.
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while let Some(header) = webp.get(pos..pos + 8) {
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = (pos + 8 + len + (len & 1)).min(webp.len());
+        let chunk = webp
+            .get(pos..pos + 8 + len)
+            .map(|_| &webp[pos..end])
+            .ok_or_else(|| "Truncated WebP chunk".to_string())?;
+        match &chunk[..4] {
+            b"EXIF" | b"XMP " => {}
+            b"VP8X" if len >= 1 => {
+                // Clear the EXIF (0x08) and XMP (0x04) flags.
+                let mut vp8x = chunk.to_vec();
+                vp8x[8] &= !0x0C;
+                chunks.push(Cow::Owned(vp8x));
+            }
+            _ => chunks.push(Cow::Borrowed(chunk)),
+        }
+        pos = end;
+    }
+
+    let body_len: usize = 4 + chunks.iter().map(|c| c.len()).sum::<usize>();
+    let mut header = b"RIFF".to_vec();
+    header.extend_from_slice(&(body_len as u32).to_le_bytes());
+    header.extend_from_slice(b"WEBP");
+
+    let mut parts = vec![Cow::Owned(header)];
+    parts.extend(chunks);
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A PNG chunk. `canonical_parts` does not check CRCs, so it is zeroed.
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut out = (data.len() as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(data);
+        out.extend_from_slice(&[0; 4]);
+        out
+    }
+
+    fn apng(second_frame: &[u8]) -> Vec<u8> {
+        let mut png = PNG_SIGNATURE.to_vec();
+        for (kind, data) in [
+            (b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 2, 0, 0, 0][..]),
+            (b"acTL", &[0, 0, 0, 2, 0, 0, 0, 0]),
+            (b"fcTL", &[0; 26]),
+            (b"IDAT", b"first frame"),
+            (b"fcTL", &[0; 26]),
+            (b"fdAT", second_frame),
+            (b"IEND", &[]),
+        ] {
+            png.extend(chunk(kind, data));
+        }
+        png
+    }
+
+    fn canonical_hash(buffer: &[u8]) -> blake3::Hash {
+        let mut hasher = blake3::Hasher::new();
+        for part in canonical_parts(buffer).unwrap() {
+            hasher.update(&part);
+        }
+        hasher.finalize()
+    }
+
+    #[test]
+    fn apng_frames_after_the_first_are_hashed() {
+        let a = apng(b"\0\0\0\x02second frame");
+        let b = apng(b"\0\0\0\x02other frame");
+        assert_ne!(canonical_hash(&a), canonical_hash(&b));
+    }
+}
@@ -1,6 +1,9 @@
+mod canonical;
+
 use wasm_bindgen::prelude::*;
 pub use wasm_bindgen_rayon::init_thread_pool;
 use blake3::Hasher;
+use js_sys::{Array, Object, Reflect, Uint8Array};
 
 /// Fast single-pass hashing for small buffers.
 #[wasm_bindgen]
@@ -8,12 +11,154 @@ pub fn hash_asset(buffer: &[u8]) -> String {
     blake3::hash(buffer).to_hex().to_string()
 }
 
+/// Hash `buffer` and write the 64 ASCII hex digits into `out`, avoiding a JS
+/// string allocation per call. `out` must hold at least 64 bytes.
+#[wasm_bindgen]
+pub fn hash_asset_into(buffer: &[u8], out: &mut [u8]) -> Result<(), JsError> {
+    write_hex(&blake3::hash(buffer), out)
+}
+
+/// A finished hash together with how many input bytes went into it.
+#[wasm_bindgen]
+pub struct HashResult {
+    hash: blake3::Hash,
+    hashed_bytes: u32,
+}
+
+#[wasm_bindgen]
+impl HashResult {
+    /// The hash as 64 hex digits.
+    #[wasm_bindgen(getter)]
+    pub fn hash(&self) -> String {
+        self.hash.to_hex().to_string()
+    }
+
+    /// The hash as raw bytes (32 bytes).
+    #[wasm_bindgen(getter)]
+    pub fn raw(&self) -> Vec<u8> {
+        self.hash.as_bytes().to_vec()
+    }
+
+    /// Number of input bytes that were hashed.
+    #[wasm_bindgen(getter, js_name = hashedBytes)]
+    pub fn hashed_bytes(&self) -> u32 {
+        self.hashed_bytes
+    }
+
+    /// Write the 64 hex digits into `out` without allocating a string.
+    #[wasm_bindgen(js_name = hashInto)]
+    pub fn hash_into(&self, out: &mut [u8]) -> Result<(), JsError> {
+        write_hex(&self.hash, out)
+    }
+}
+
+/// Hash only the first `n` bytes of `buffer` (all of it if shorter).
+///
+/// Meant as a cheap first stage for dedup: different prefix hashes prove the
+/// files differ, but equal ones do not prove they match, since the rest of
+/// the file is never read. Confirm candidates with `hash_asset` before
+/// treating them as duplicates.
+#[wasm_bindgen]
+pub fn hash_prefix(buffer: &[u8], n: u32) -> HashResult {
+    let prefix = &buffer[..buffer.len().min(n as usize)];
+    HashResult {
+        hash: blake3::hash(prefix),
+        // At most `n`, so it fits.
+        hashed_bytes: prefix.len() as u32,
+    }
+}
+
+/// Hash the decoded pixels of `buffer` instead of its bytes, so files that
+/// differ only in metadata (EXIF edits, re-tagging) or container details
+/// hash the same. The input is the width and height as little-endian u32
+/// followed by the pixels as 8-bit RGBA; `hashedBytes` counts those pixel
+/// bytes.
+///
+/// Much slower than `hash_asset`, since the image is fully decoded. It
+/// identifies decoded pixels, not files: lossless re-encodes match, lossy
+/// re-encodes do not, and sources deeper than 8 bits are reduced to 8 first.
+///
+/// Only in builds with the opt-in `pixels` feature.
+#[cfg(feature = "pixels")]
+#[wasm_bindgen]
+pub fn hash_pixels(buffer: &[u8]) -> Result<HashResult, JsError> {
+    let img = image::load_from_memory(buffer)
+        .map_err(|e| JsError::new(&format!("Failed to decode image: {}", e)))?;
+    let rgba = img.to_rgba8();
+
+    let mut hasher = Hasher::new();
+    hasher.update(&rgba.width().to_le_bytes());
+    hasher.update(&rgba.height().to_le_bytes());
+    hasher.update(rgba.as_raw());
+    Ok(HashResult {
+        hash: hasher.finalize(),
+        hashed_bytes: u32::try_from(rgba.as_raw().len())
+            .map_err(|_| JsError::new("Image has more than 4 GiB of pixel data"))?,
+    })
+}
+
+/// Hash `buffer` with its metadata stripped, so files that differ only in
+/// EXIF, XMP, comments or text chunks hash the same. The container is
+/// re-muxed without touching the pixel data, which is much cheaper than
+/// `hash_pixels`. `hashedBytes` counts the stripped file.
+///
+/// Supported: JPEG (APPn and COM segments, keeping the Adobe APP14 and ICC
+/// profile), PNG (ancillary chunks that do not affect rendering) and WebP
+/// (EXIF and XMP chunks). Anything else is an error. Unlike `hash_pixels`,
+/// the same pixels saved with different encoder settings hash differently.
+#[wasm_bindgen]
+pub fn hash_canonical(buffer: &[u8]) -> Result<HashResult, JsError> {
+    let parts = canonical::canonical_parts(buffer).map_err(|e| JsError::new(&e))?;
+
+    let mut hasher = Hasher::new();
+    let mut hashed_bytes = 0u64;
+    for part in &parts {
+        hasher.update(part);
+        hashed_bytes += part.len() as u64;
+    }
+    Ok(HashResult {
+        hash: hasher.finalize(),
+        // Never more than `buffer` plus a rebuilt 12-byte RIFF header.
+        hashed_bytes: u32::try_from(hashed_bytes)
+            .map_err(|_| JsError::new("Buffer is larger than 4 GiB"))?,
+    })
+}
+
+/// Hash of `buffer` chained to the previous entry: the 32 bytes `prev_hash`
+/// encodes (64 hex digits, either case) followed by `buffer`. Each link
+/// depends on every earlier one, so editing, dropping or reordering an
+/// entry in an append-only log changes every hash after it. Start a chain
+/// with a fixed value such as 64 zeros. `hashedBytes` counts `buffer` only.
+#[wasm_bindgen]
+pub fn hash_chained(prev_hash: &str, buffer: &[u8]) -> Result<HashResult, JsError> {
+    if !is_valid_hash(prev_hash, blake3::OUT_LEN as u32) {
+        return Err(JsError::new("Previous hash must be 64 hex digits"));
+    }
+    let prev = blake3::Hash::from_hex(prev_hash)
+        .map_err(|e| JsError::new(&format!("Invalid previous hash: {}", e)))?;
+
+    let mut hasher = Hasher::new();
+    hasher.update(prev.as_bytes());
+    hasher.update(buffer);
+    Ok(HashResult {
+        hash: hasher.finalize(),
+        hashed_bytes: u32::try_from(buffer.len())
+            .map_err(|_| JsError::new("Buffer is larger than 4 GiB"))?,
+    })
+}
+
 /// Streaming hasher for large files to maintain low memory usage.
 #[wasm_bindgen]
 pub struct StreamingHasher {
     inner: Hasher,
 }
 
+impl Default for StreamingHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[wasm_bindgen]
 impl StreamingHasher {
     #[wasm_bindgen(constructor)]
@@ -33,6 +178,12 @@ impl StreamingHasher {
         self.inner.finalize().to_hex().to_string()
     }
 
+    /// Finalize the hash and write its 64 hex digits into `out`.
+    #[wasm_bindgen(js_name = finalizeInto)]
+    pub fn finalize_into(self, out: &mut [u8]) -> Result<(), JsError> {
+        write_hex(&self.inner.finalize(), out)
+    }
+
     /// Finalize the hash and return as raw bytes (32 bytes).
     #[wasm_bindgen(js_name = finalizeRaw)]
     pub fn finalize_raw(self) -> Vec<u8> {
@@ -40,9 +191,96 @@ impl StreamingHasher {
     }
 }
 
-/// Verify if a buffer's hash matches the expected hex string.
+/// Copy the hex form of `hash` into `out`. `to_hex` formats on the stack, so
+/// no heap allocation happens.
+fn write_hex(hash: &blake3::Hash, out: &mut [u8]) -> Result<(), JsError> {
+    let hex = hash.to_hex();
+    let target = out.get_mut(..hex.len()).ok_or_else(|| {
+        JsError::new(&format!("Output buffer must hold at least {} bytes", hex.len()))
+    })?;
+    target.copy_from_slice(hex.as_bytes());
+    Ok(())
+}
+
+/// Whether `hash_string` is exactly `expected_bytes * 2` hex digits, in
+/// either case.
+#[wasm_bindgen]
+pub fn is_valid_hash(hash_string: &str, expected_bytes: u32) -> bool {
+    hash_string.len() as u64 == expected_bytes as u64 * 2
+        && hash_string.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Verify if a buffer's hash matches the expected hex string. Digits may be
+/// upper or lower case; anything but 64 hex digits never matches.
 #[wasm_bindgen]
 pub fn verify_asset_hash(buffer: &[u8], expected_hex: &str) -> bool {
+    if !is_valid_hash(expected_hex, blake3::OUT_LEN as u32) {
+        return false;
+    }
     let hash_bytes = blake3::hash(buffer);
-    hash_bytes.to_hex().as_str() == expected_hex
-}
\ No newline at end of file
+    hash_bytes.to_hex().as_str().eq_ignore_ascii_case(expected_hex)
+}
+
+/// Bytes compared at the start, middle and end by `compare_assets` before
+/// the full comparison.
+const COMPARE_SAMPLE_LEN: usize = 4096;
+
+/// Whether `a` and `b` are byte-identical.
+///
+/// Cheap checks run first: the lengths, then three sampled ranges at the
+/// start, middle and end, where differing files (other headers, other
+/// trailing metadata) usually already differ. Those only ever reject. When
+/// they all match, the buffers are compared in full, so the answer is exact;
+/// comparing bytes directly also beats hashing both, which reads the same
+/// data and does more work per byte.
+#[wasm_bindgen]
+pub fn compare_assets(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let len = a.len();
+    let sample = COMPARE_SAMPLE_LEN.min(len);
+    let middle = (len - sample) / 2;
+    for start in [0, middle, len - sample] {
+        if a[start..start + sample] != b[start..start + sample] {
+            return false;
+        }
+    }
+    a == b
+}
+
+/// Hash every buffer in `buffers`, returning `{ index, ok, result | error }`
+/// per item. A bad item only fails itself unless `fail_fast` is set, in which
+/// case the first failure is thrown.
+#[wasm_bindgen]
+pub fn hash_assets_batch(buffers: Array, fail_fast: Option<bool>) -> Result<Array, JsError> {
+    let fail_fast = fail_fast.unwrap_or(false);
+    let items = Array::new();
+
+    for (index, value) in buffers.iter().enumerate() {
+        let item = Object::new();
+        set_field(&item, "index", &JsValue::from(index as u32))?;
+        match value.dyn_into::<Uint8Array>() {
+            Ok(bytes) => {
+                set_field(&item, "ok", &JsValue::TRUE)?;
+                set_field(&item, "result", &JsValue::from(hash_asset(&bytes.to_vec())))?;
+            }
+            Err(_) if fail_fast => {
+                return Err(JsError::new(&format!("Batch item {} is not a Uint8Array", index)));
+            }
+            Err(_) => {
+                set_field(&item, "ok", &JsValue::FALSE)?;
+                set_field(&item, "error", &JsValue::from_str("Batch item is not a Uint8Array"))?;
+            }
+        }
+        items.push(&item);
+    }
+
+    Ok(items)
+}
+
+fn set_field(target: &Object, key: &str, value: &JsValue) -> Result<(), JsError> {
+    Reflect::set(target, &JsValue::from_str(key), value)
+        .map(|_| ())
+        .map_err(|_| JsError::new(&format!("Failed to set batch field `{}`", key)))
+}
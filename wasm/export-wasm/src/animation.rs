@@ -0,0 +1,151 @@
+use image::{
+    codecs::{gif::GifDecoder, png::PngDecoder, webp::WebPDecoder, webp::WebPEncoder},
+    AnimationDecoder, DynamicImage, ExtendedColorType, Frames, GenericImageView, ImageFormat,
+};
+use std::io::Cursor;
+
+/// Largest value of the 24-bit fields in VP8X and ANMF.
+const MAX_U24: u32 = (1 << 24) - 1;
+
+/// Most frames read from one animated input.
+pub const MAX_FRAMES: usize = 1000;
+
+/// Frames of an animated GIF, WebP or PNG, each composited onto the full
+/// canvas, or `None` when `bytes` is not one of those animations. Frames
+/// decode lazily, one at a time.
+pub fn decode_frames(bytes: &[u8]) -> Result<Option<Frames<'_>>, String> {
+    let error = |e: image::ImageError| e.to_string();
+    let frames = match image::guess_format(bytes).ok() {
+        Some(ImageFormat::Gif) => GifDecoder::new(Cursor::new(bytes)).map_err(error)?.into_frames(),
+        Some(ImageFormat::WebP) => {
+            let decoder = WebPDecoder::new(Cursor::new(bytes)).map_err(error)?;
+            if !decoder.has_animation() {
+                return Ok(None);
+            }
+            decoder.into_frames()
+        }
+        Some(ImageFormat::Png) => {
+            let decoder = PngDecoder::new(Cursor::new(bytes)).map_err(error)?;
+            if !decoder.is_apng().map_err(error)? {
+                return Ok(None);
+            }
+            decoder.apng().map_err(error)?.into_frames()
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(frames))
+}
+
+/// Assemble `frames` into an extended-format animated WebP.
+///
+/// Each frame is encoded lossless on its own and wrapped in an ANMF chunk
+/// covering the whole canvas with blending off, so frames replace each other
+/// rather than compositing. `loop_count` 0 loops forever.
+pub fn encode_animated_webp(
+    frames: &[DynamicImage],
+    delays_ms: &[u32],
+    loop_count: u16,
+) -> Result<Vec<u8>, String> {
+    let first = frames.first().ok_or("At least one frame is required")?;
+    if delays_ms.len() != frames.len() {
+        return Err(format!(
+            "Got {} delays for {} frames",
+            delays_ms.len(),
+            frames.len()
+        ));
+    }
+    let (width, height) = first.dimensions();
+    if let Some(index) = frames.iter().position(|f| f.dimensions() != (width, height)) {
+        let (w, h) = frames[index].dimensions();
+        return Err(format!(
+            "Frame {} is {}x{}, expected {}x{}",
+            index, w, h, width, height
+        ));
+    }
+    if let Some(delay) = delays_ms.iter().find(|&&d| d > MAX_U24) {
+        return Err(format!("Frame delay of {} ms is too long", delay));
+    }
+
+    let has_alpha = frames.iter().any(|f| f.color().has_alpha());
+
+    let mut vp8x = Vec::with_capacity(10);
+    // Animation flag, plus alpha when any frame has it.
+    vp8x.push(if has_alpha { 0x12 } else { 0x02 });
+    vp8x.extend_from_slice(&[0, 0, 0]);
+    vp8x.extend_from_slice(&u24(width - 1));
+    vp8x.extend_from_slice(&u24(height - 1));
+
+    // Background color (BGRA, transparent) and loop count.
+    let mut anim = vec![0, 0, 0, 0];
+    anim.extend_from_slice(&loop_count.to_le_bytes());
+
+    let mut body = b"WEBP".to_vec();
+    push_chunk(&mut body, b"VP8X", &vp8x);
+    push_chunk(&mut body, b"ANIM", &anim);
+    for (frame, &delay) in frames.iter().zip(delays_ms) {
+        let bitstream = encode_frame(frame)?;
+
+        // Offset 0,0, full-canvas size, duration, then "do not blend".
+        let mut anmf = Vec::with_capacity(16 + bitstream.len());
+        anmf.extend_from_slice(&u24(0));
+        anmf.extend_from_slice(&u24(0));
+        anmf.extend_from_slice(&u24(width - 1));
+        anmf.extend_from_slice(&u24(height - 1));
+        anmf.extend_from_slice(&u24(delay));
+        anmf.push(0x02);
+        anmf.extend_from_slice(&bitstream);
+        push_chunk(&mut body, b"ANMF", &anmf);
+    }
+
+    let size = u32::try_from(body.len()).map_err(|_| "Animation is too large".to_string())?;
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Encode one frame as a lossless WebP and return its VP8L chunk, header and
+/// padding included, ready to nest in an ANMF chunk.
+fn encode_frame(frame: &DynamicImage) -> Result<Vec<u8>, String> {
+    let rgba = frame.to_rgba8();
+    let mut encoded = Vec::new();
+    WebPEncoder::new_lossless(&mut encoded)
+        .encode(
+            rgba.as_raw(),
+            rgba.width(),
+            rgba.height(),
+            ExtendedColorType::Rgba8,
+        )
+        .map_err(|e| format!("WebP encoding error: {}", e))?;
+
+    // Walk the chunks after the 12-byte RIFF/WEBP header.
+    let mut pos = 12;
+    while let Some(header) = encoded.get(pos..pos + 8) {
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let end = pos + 8 + len + (len & 1);
+        if &header[..4] == b"VP8L" {
+            return encoded
+                .get(pos..end.min(encoded.len()))
+                .map(<[u8]>::to_vec)
+                .ok_or_else(|| "Truncated VP8L chunk".to_string());
+        }
+        pos = end;
+    }
+    Err("Encoder produced no VP8L chunk".to_string())
+}
+
+fn push_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    // Chunks are padded to an even length.
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let [a, b, c, _] = value.to_le_bytes();
+    [a, b, c]
+}
@@ -0,0 +1,72 @@
+use image::{imageops, DynamicImage, RgbaImage};
+use serde::Serialize;
+
+/// Where one input image landed in the atlas, in atlas pixels.
+#[derive(Serialize, Debug, Clone, Copy, Default)]
+pub struct Placement {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/// Shelf-pack rectangles of `sizes` into rows at most `max_width` wide,
+/// with `padding` pixels between neighbours but none at the atlas edges.
+///
+/// Rectangles are placed tallest first, left to right, opening a new shelf
+/// below when the current one is full. Returns the placements in input
+/// order and the atlas size, trimmed to what is used.
+pub fn shelf_pack(
+    sizes: &[(u32, u32)],
+    max_width: u32,
+    padding: u32,
+) -> Result<(Vec<Placement>, u32, u32), String> {
+    if let Some(index) = sizes.iter().position(|&(w, _)| w > max_width) {
+        return Err(format!(
+            "Image {} is {} px wide, more than the atlas max_width of {}",
+            index, sizes[index].0, max_width
+        ));
+    }
+
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    // Stable, so equal heights keep input order.
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+
+    let mut placements = vec![Placement::default(); sizes.len()];
+    let (mut x, mut shelf_y, mut shelf_h) = (0u64, 0u64, 0u64);
+    let mut width = 0u64;
+    for index in order {
+        let (w, h) = (sizes[index].0 as u64, sizes[index].1 as u64);
+        if x > 0 && x + padding as u64 + w > max_width as u64 {
+            shelf_y += shelf_h + padding as u64;
+            x = 0;
+            shelf_h = 0;
+        }
+        if x > 0 {
+            x += padding as u64;
+        }
+        let y = u32::try_from(shelf_y).map_err(|_| "Atlas is too tall".to_string())?;
+        placements[index] = Placement {
+            x: x as u32,
+            y,
+            w: w as u32,
+            h: h as u32,
+        };
+        x += w;
+        width = width.max(x);
+        shelf_h = shelf_h.max(h);
+    }
+
+    let height = u32::try_from(shelf_y + shelf_h).map_err(|_| "Atlas is too tall".to_string())?;
+    Ok((placements, width as u32, height))
+}
+
+/// Draw `images` at their `placements` on a transparent `width`×`height`
+/// canvas.
+pub fn compose(images: &[DynamicImage], placements: &[Placement], width: u32, height: u32) -> RgbaImage {
+    let mut atlas = RgbaImage::new(width, height);
+    for (img, placement) in images.iter().zip(placements) {
+        imageops::replace(&mut atlas, &img.to_rgba8(), placement.x as i64, placement.y as i64);
+    }
+    atlas
+}
@@ -0,0 +1,284 @@
+use image::DynamicImage;
+
+/// Decode an encoded image, routing JPEG through the decoder selected at
+/// build time.
+///
+/// PNG `tRNS` transparency (palette entries, or a gray/RGB color key) is
+/// expanded by `image`'s PNG decoder into a real alpha channel (Rgba8/La8),
+/// so it survives resizing and re-encoding to PNG or WebP; `png_palette`
+/// output writes it back as `tRNS`.
+///
+/// Inputs failing `check_input` are rejected before any decoder runs.
+pub fn decode_image(bytes: &[u8]) -> Result<DynamicImage, String> {
+    check_input(bytes)?;
+    decode_unchecked(bytes)
+}
+
+fn decode_unchecked(bytes: &[u8]) -> Result<DynamicImage, String> {
+    #[cfg(test)]
+    tests::maybe_panic();
+
+    if is_heif(bytes) {
+        return decode_heif(bytes);
+    }
+
+    #[cfg(feature = "jpeg-decoder")]
+    if image::guess_format(bytes).ok() == Some(image::ImageFormat::Jpeg) {
+        return decode_jpeg(bytes, None).map(|(img, _)| img);
+    }
+
+    image::load_from_memory(bytes).map_err(|e| e.to_string())
+}
+
+/// Decode so the result fits within `max_dimension` on both sides, along
+/// with the full size of the source.
+///
+/// JPEGs always go through `jpeg-decoder` here, whatever the build's
+/// decoder feature, and are decoded at 1/2, 1/4 or 1/8 scale straight from
+/// the DCT coefficients, so the full-size pixels never exist in memory.
+/// Every other format decodes at full size and downscales.
+pub fn decode_image_bounded(
+    bytes: &[u8],
+    max_dimension: u32,
+) -> Result<(DynamicImage, (u32, u32)), String> {
+    check_input(bytes)?;
+
+    if !is_heif(bytes) && image::guess_format(bytes).ok() == Some(image::ImageFormat::Jpeg) {
+        let (img, full_size) = decode_jpeg(bytes, Some(max_dimension))?;
+        return Ok((fit_within(img, max_dimension), full_size));
+    }
+
+    let img = decode_unchecked(bytes)?;
+    let full_size = (img.width(), img.height());
+    Ok((fit_within(img, max_dimension), full_size))
+}
+
+fn fit_within(img: DynamicImage, max_dimension: u32) -> DynamicImage {
+    if img.width() <= max_dimension && img.height() <= max_dimension {
+        img
+    } else {
+        img.resize(max_dimension, max_dimension, image::imageops::FilterType::Lanczos3)
+    }
+}
+
+/// Largest encoded input accepted, in bytes.
+pub const MAX_INPUT_BYTES: usize = 256 * 1024 * 1024;
+/// Largest source accepted, in pixels: 128 MP, whose RGBA8 buffer is
+/// `image`'s default 512 MiB allocation limit.
+pub const MAX_INPUT_PIXELS: u64 = 128 * 1024 * 1024;
+
+/// Screen `bytes` from its header alone: the size must be within
+/// `MAX_INPUT_BYTES`, the header must parse, and the frame must be non-empty
+/// and within `MAX_INPUT_PIXELS`.
+///
+/// wasm32 builds abort on panic, so a decoder panic cannot be caught there
+/// (see `utils::catch_panic`). Rejecting truncated, unrecognised and
+/// oversized inputs here keeps them away from the decoders entirely. HEIF
+/// headers are left to libheif.
+pub fn check_input(bytes: &[u8]) -> Result<(), String> {
+    if bytes.len() > MAX_INPUT_BYTES {
+        return Err(format!(
+            "Input is {} bytes, more than the {} byte limit",
+            bytes.len(),
+            MAX_INPUT_BYTES
+        ));
+    }
+    if is_heif(bytes) {
+        return Ok(());
+    }
+
+    let (width, height) = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("Unreadable image header: {}", e))?;
+    if width == 0 || height == 0 {
+        return Err("Image has zero width or height".to_string());
+    }
+    if width as u64 * height as u64 > MAX_INPUT_PIXELS {
+        return Err(format!(
+            "Image is {}x{}, more than the {} pixel limit",
+            width, height, MAX_INPUT_PIXELS
+        ));
+    }
+    Ok(())
+}
+
+/// Size of the RGBA8 pixel buffer `bytes` decodes to, from its header alone.
+/// `None` when the header cannot be read without a full decode.
+pub fn estimate_decoded_bytes(bytes: &[u8]) -> Option<u64> {
+    let (width, height) = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()?;
+    Some(width as u64 * height as u64 * 4)
+}
+
+/// ISO-BMFF `ftyp` brands written by HEIF/HEIC encoders.
+const HEIF_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+pub fn is_heif(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && HEIF_BRANDS.iter().any(|b| &bytes[8..12] == *b)
+}
+
+/// Decode the primary HEIF image through libheif, which also applies the
+/// container's rotation, mirroring and crop.
+#[cfg(feature = "heif")]
+fn decode_heif(bytes: &[u8]) -> Result<DynamicImage, String> {
+    use image::RgbaImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = context.primary_image_handle().map_err(|e| e.to_string())?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(|e| e.to_string())?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or("HEIF image has no interleaved RGBA plane")?;
+
+    let row_len = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "HEIF buffer does not match its dimensions".to_string())
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_bytes: &[u8]) -> Result<DynamicImage, String> {
+    Err("Unsupported format: HEIF/HEIC decoding requires the `heif` feature".to_string())
+}
+
+/// Decode JPEG through `jpeg-decoder` rather than `image`'s zune-jpeg backend,
+/// returning the image and the full frame size. With `max_dimension` the
+/// decoder picks the smallest DCT scale whose output still covers the fitted
+/// size.
+fn decode_jpeg(
+    bytes: &[u8],
+    max_dimension: Option<u32>,
+) -> Result<(DynamicImage, (u32, u32)), String> {
+    use image::{GrayImage, ImageBuffer, Luma, RgbImage};
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    let mut decoder = Decoder::new(std::io::Cursor::new(bytes));
+    decoder.read_info().map_err(|e| e.to_string())?;
+    let frame = decoder.info().ok_or("Missing JPEG frame info")?;
+    let full_size = (frame.width as u32, frame.height as u32);
+    if let Some(max) = max_dimension {
+        let scale = (max as f64 / full_size.0.max(full_size.1) as f64).min(1.0);
+        let requested_w = (full_size.0 as f64 * scale).ceil().max(1.0) as u16;
+        let requested_h = (full_size.1 as f64 * scale).ceil().max(1.0) as u16;
+        decoder
+            .scale(requested_w, requested_h)
+            .map_err(|e| e.to_string())?;
+    }
+
+    let pixels = decoder.decode().map_err(|e| e.to_string())?;
+    let info = decoder.info().ok_or("Missing JPEG frame info")?;
+    let (width, height) = (info.width as u32, info.height as u32);
+
+    let img = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::L16 => {
+            let samples = pixels
+                .chunks_exact(2)
+                .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+                .collect();
+            ImageBuffer::<Luma<u16>, _>::from_raw(width, height, samples)
+                .map(DynamicImage::ImageLuma16)
+        }
+        PixelFormat::RGB24 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        PixelFormat::CMYK32 => {
+            // `jpeg-decoder` has already undone the Adobe APP14 transform
+            // (YCCK) and inversion, so these are plain CMYK. Same formula as
+            // zune-jpeg, see wasm/README.md.
+            let rgb = pixels
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let k = 255 - p[3] as u16;
+                    [0, 1, 2].map(|i| ((255 - p[i] as u16) * k / 255) as u8)
+                })
+                .collect();
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+    };
+
+    img.map(|img| (img, full_size))
+        .ok_or_else(|| "JPEG buffer does not match its dimensions".to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    thread_local! {
+        static PANIC_ON_DECODE: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Make the next decode on this thread panic after `check_input` has
+    /// passed, standing in for a decoder bug on a well-formed header.
+    pub(crate) fn panic_on_next_decode() {
+        PANIC_ON_DECODE.with(|flag| flag.set(true));
+    }
+
+    pub(super) fn maybe_panic() {
+        if PANIC_ON_DECODE.with(|flag| flag.replace(false)) {
+            panic!("crafted decoder panic");
+        }
+    }
+
+    fn jpeg_bytes(img: &DynamicImage) -> Vec<u8> {
+        let mut out = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut out), image::ImageFormat::Jpeg)
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn bounded_jpeg_decode_scales_in_the_decoder() {
+        let jpeg = jpeg_bytes(&DynamicImage::new_rgb8(800, 600));
+
+        // 1/8 scale covers a 100 px bound, so no full-size pass is needed.
+        let (img, full_size) = decode_jpeg(&jpeg, Some(100)).unwrap();
+        assert_eq!((img.width(), img.height()), (100, 75));
+        assert_eq!(full_size, (800, 600));
+
+        let (img, full_size) = decode_image_bounded(&jpeg, 300).unwrap();
+        assert_eq!((img.width(), img.height()), (300, 225));
+        assert_eq!(full_size, (800, 600));
+    }
+    #[test]
+    fn adobe_cmyk_and_ycck_jpegs_are_not_inverted() {
+        // Inverted CMYK 204,102,255,230 is C 20%, M 60%, Y 0%, K 10%, which
+        // `(1 - C)(1 - K)` maps to about RGB 184,92,230.
+        let fixtures: [(&str, &[u8]); 2] = [
+            ("cmyk", include_bytes!("../tests/fixtures/adobe_cmyk.jpg")),
+            ("ycck", include_bytes!("../tests/fixtures/adobe_ycck.jpg")),
+        ];
+        for (name, jpeg) in fixtures {
+            let default = decode_image(jpeg).unwrap().to_rgb8();
+            let (alternate, _) = decode_jpeg(jpeg, None).unwrap();
+            for (decoder, img) in [("default", default), ("jpeg-decoder", alternate.to_rgb8())] {
+                let pixel = img.get_pixel(4, 4).0;
+                for (c, expected) in [184u8, 92, 230].into_iter().enumerate() {
+                    assert!(
+                        pixel[c].abs_diff(expected) <= 3,
+                        "{} via {}: {:?}",
+                        name,
+                        decoder,
+                        pixel
+                    );
+                }
+            }
+        }
+    }
+}
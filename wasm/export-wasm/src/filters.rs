@@ -0,0 +1,416 @@
+use image::{DynamicImage, GrayImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// Kernel edge lengths accepted by `convolve`.
+pub const KERNEL_SIZES: [u32; 2] = [3, 5];
+
+/// Convolve the color channels with a square `size`×`size` kernel given in
+/// row-major order.
+///
+/// Weights are divided by their sum so blur/sharpen kernels keep overall
+/// brightness, except when the sum is zero (edge-detect, emboss), where they
+/// are applied as given. Samples past the border repeat the edge pixel, and
+/// alpha is left untouched.
+pub fn convolve(img: &DynamicImage, kernel: &[f32], size: u32) -> Result<DynamicImage, String> {
+    if !KERNEL_SIZES.contains(&size) {
+        return Err(format!("Unsupported kernel size: {}", size));
+    }
+    if kernel.len() != (size * size) as usize {
+        return Err(format!(
+            "Kernel of size {} needs {} weights, got {}",
+            size,
+            size * size,
+            kernel.len()
+        ));
+    }
+
+    let sum: f32 = kernel.iter().sum();
+    let scale = if sum == 0.0 { 1.0 } else { 1.0 / sum };
+    let radius = (size / 2) as i64;
+
+    let src = img.to_rgba8();
+    let (width, height) = src.dimensions();
+    let out = RgbaImage::from_fn(width, height, |x, y| {
+        let mut acc = [0.0f32; 3];
+        for (i, &weight) in kernel.iter().enumerate() {
+            let kx = (i as u32 % size) as i64 - radius;
+            let ky = (i as u32 / size) as i64 - radius;
+            let sx = (x as i64 + kx).clamp(0, width as i64 - 1) as u32;
+            let sy = (y as i64 + ky).clamp(0, height as i64 - 1) as u32;
+            let p = src.get_pixel(sx, sy);
+            for c in 0..3 {
+                acc[c] += p[c] as f32 * weight;
+            }
+        }
+        let alpha = src.get_pixel(x, y)[3];
+        Rgba([
+            (acc[0] * scale).round().clamp(0.0, 255.0) as u8,
+            (acc[1] * scale).round().clamp(0.0, 255.0) as u8,
+            (acc[2] * scale).round().clamp(0.0, 255.0) as u8,
+            alpha,
+        ])
+    });
+
+    Ok(if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(out)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(out).to_rgb8())
+    })
+}
+
+/// WebP-style near-lossless preprocessing ahead of a lossless encode.
+///
+/// `level` runs from 100 (unchanged) down to 0. It sets how many low bits
+/// are dropped, as in libwebp: `5 - level / 20`, so 80 drops one bit and 0
+/// drops five. Each color channel is rounded to that step, moving it by at
+/// most half a step, which flattens noise and gradients for the lossless
+/// encoder. Pixels next to a hard edge (a neighbour four or more steps away
+/// in any channel) stay exact, so text and line art keep their edges. The
+/// image border and alpha are left untouched.
+pub fn near_lossless(img: &DynamicImage, level: u8) -> DynamicImage {
+    let bits = 5 - level.min(100) / 20;
+    if bits == 0 {
+        return img.clone();
+    }
+    let step = 1i16 << bits;
+
+    let src = img.to_rgba8();
+    let mut out = src.clone();
+    let (width, height) = src.dimensions();
+    for y in 1..height.saturating_sub(1) {
+        for x in 1..width.saturating_sub(1) {
+            let center = src.get_pixel(x, y);
+            let neighbours = [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)];
+            let smooth = neighbours.iter().all(|&(nx, ny)| {
+                let n = src.get_pixel(nx, ny);
+                (0..3).all(|c| (n[c] as i16 - center[c] as i16).abs() < step * 4)
+            });
+            if !smooth {
+                continue;
+            }
+            let pixel = out.get_pixel_mut(x, y);
+            for c in 0..3 {
+                let v = pixel[c] as i16;
+                pixel[c] = ((v + step / 2) & !(step - 1)).min(255) as u8;
+            }
+        }
+    }
+
+    if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(out)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(out).to_rgb8())
+    }
+}
+
+/// Reduce `img` to at most `max_colors` (1-256) RGBA colors by median cut.
+/// Returns the palette and one palette index per pixel, row by row.
+///
+/// Fully transparent pixels share a single `[0, 0, 0, 0]` entry. The other
+/// distinct colors, weighted by how many pixels use them, start in one box;
+/// the box with the widest channel range (alpha included) is split at the
+/// weighted median of that channel until the palette is full or no box holds
+/// more than one color. Each box becomes the weighted mean of its colors,
+/// and every pixel maps to its own color's box, so the result is
+/// deterministic and colors that already fit are kept exactly.
+pub fn median_cut(img: &RgbaImage, max_colors: usize) -> (Vec<[u8; 4]>, Vec<u8>) {
+    let max_colors = max_colors.clamp(1, 256);
+    let mut counts: std::collections::HashMap<[u8; 4], u32> = std::collections::HashMap::new();
+    let mut transparent = false;
+    for pixel in img.pixels() {
+        if pixel[3] == 0 {
+            transparent = true;
+        } else {
+            *counts.entry(pixel.0).or_insert(0) += 1;
+        }
+    }
+    // Sorted so the splits, and with them the palette, do not depend on
+    // hash order.
+    let mut colors: Vec<([u8; 4], u32)> = counts.into_iter().collect();
+    colors.sort_unstable();
+
+    let budget = max_colors - transparent as usize;
+    let mut boxes = Vec::new();
+    if !colors.is_empty() {
+        boxes.push(0..colors.len());
+    }
+    while boxes.len() < budget {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, range)| range.len() > 1)
+            .map(|(i, range)| {
+                let (channel, spread) = (0..4)
+                    .map(|c| {
+                        let values = colors[range.clone()].iter().map(|(color, _)| color[c]);
+                        let (lo, hi) = values.fold((255, 0), |(lo, hi), v| (lo.min(v), hi.max(v)));
+                        (c, hi - lo)
+                    })
+                    .max_by_key(|&(c, spread)| (spread, std::cmp::Reverse(c)))
+                    .unwrap();
+                (i, channel, spread)
+            })
+            .max_by_key(|&(i, _, spread)| (spread, std::cmp::Reverse(i)));
+        let Some((i, channel, _)) = widest else {
+            break;
+        };
+
+        let range = boxes[i].clone();
+        let slice = &mut colors[range.clone()];
+        slice.sort_by_key(|(color, _)| color[channel]);
+        let total: u64 = slice.iter().map(|&(_, n)| n as u64).sum();
+        let mut seen = 0u64;
+        let median = slice
+            .iter()
+            .position(|&(_, n)| {
+                seen += n as u64;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        // Both halves must be non-empty.
+        let split = range.start + (median + 1).clamp(1, slice.len() - 1);
+        boxes[i] = range.start..split;
+        boxes.push(split..range.end);
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len() + transparent as usize);
+    let mut lookup = std::collections::HashMap::with_capacity(colors.len());
+    for range in boxes {
+        let mut sum = [0u64; 4];
+        let mut total = 0u64;
+        for &(color, n) in &colors[range.clone()] {
+            for c in 0..4 {
+                sum[c] += color[c] as u64 * n as u64;
+            }
+            total += n as u64;
+        }
+        let index = palette.len() as u8;
+        palette.push(sum.map(|s| ((s + total / 2) / total) as u8));
+        for &(color, _) in &colors[range] {
+            lookup.insert(color, index);
+        }
+    }
+    let transparent_index = palette.len() as u8;
+    if transparent {
+        palette.push([0, 0, 0, 0]);
+    }
+
+    let indices = img
+        .pixels()
+        .map(|pixel| {
+            if pixel[3] == 0 {
+                transparent_index
+            } else {
+                lookup[&pixel.0]
+            }
+        })
+        .collect();
+    (palette, indices)
+}
+
+/// Dithering methods accepted by `dither`.
+pub const DITHER_METHODS: [&str; 3] = ["floyd-steinberg", "ordered", "threshold"];
+
+/// 4×4 Bayer matrix, thresholds in sixteenths.
+const BAYER_4X4: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Reduce the image to black and white (every pixel 0 or 255) on its luma.
+/// Alpha is dropped.
+///
+/// - `floyd-steinberg`: error diffusion, best for photos
+/// - `ordered`: 4×4 Bayer pattern, stable across frames and crops
+/// - `threshold`: plain cut at mid-gray, best for line art and text
+///
+/// All three are deterministic: no method uses randomness, and the error
+/// diffusion is a fixed left-to-right, top-to-bottom scan in plain IEEE
+/// `f32`, so the same input gives the same pixels on every run and target.
+pub fn dither(img: &DynamicImage, method: &str) -> Result<GrayImage, String> {
+    let mut gray = img.to_luma8();
+    let (width, height) = gray.dimensions();
+
+    match method {
+        "threshold" => {
+            for pixel in gray.pixels_mut() {
+                pixel[0] = if pixel[0] >= 128 { 255 } else { 0 };
+            }
+        }
+        "ordered" => {
+            for (x, y, pixel) in gray.enumerate_pixels_mut() {
+                let threshold = BAYER_4X4[(y % 4) as usize][(x % 4) as usize] as u32 * 16 + 8;
+                pixel[0] = if pixel[0] as u32 >= threshold { 255 } else { 0 };
+            }
+        }
+        "floyd-steinberg" => {
+            let (w, h) = (width as usize, height as usize);
+            let mut values: Vec<f32> = gray.pixels().map(|p| p[0] as f32).collect();
+            for y in 0..h {
+                for x in 0..w {
+                    let old = values[y * w + x];
+                    let new = if old >= 128.0 { 255.0 } else { 0.0 };
+                    values[y * w + x] = new;
+                    let error = old - new;
+                    if x + 1 < w {
+                        values[y * w + x + 1] += error * 7.0 / 16.0;
+                    }
+                    if y + 1 < h {
+                        if x > 0 {
+                            values[(y + 1) * w + x - 1] += error * 3.0 / 16.0;
+                        }
+                        values[(y + 1) * w + x] += error * 5.0 / 16.0;
+                        if x + 1 < w {
+                            values[(y + 1) * w + x + 1] += error / 16.0;
+                        }
+                    }
+                }
+            }
+            for (pixel, value) in gray.pixels_mut().zip(values) {
+                pixel[0] = value as u8;
+            }
+        }
+        _ => return Err(format!("Unsupported dither method: {}", method)),
+    }
+
+    Ok(gray)
+}
+
+/// Tone curve control points as `[input, output]` pairs on 0–255.
+///
+/// `rgb` is shared by all color channels and applied after the per-channel
+/// curves. Between points the curve is linear; before the first and after
+/// the last point it stays flat.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CurvePoints {
+    pub rgb: Option<Vec<[u8; 2]>>,
+    pub red: Option<Vec<[u8; 2]>>,
+    pub green: Option<Vec<[u8; 2]>>,
+    pub blue: Option<Vec<[u8; 2]>>,
+}
+
+impl CurvePoints {
+    pub fn validate(&self) -> Result<(), String> {
+        for points in [&self.rgb, &self.red, &self.green, &self.blue].into_iter().flatten() {
+            if points.len() < 2 {
+                return Err("A curve needs at least 2 points".to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Apply `curve` to the color channels through 256-entry lookup tables.
+/// Alpha is left untouched.
+pub fn apply_curve(img: &DynamicImage, curve: &CurvePoints) -> Result<DynamicImage, String> {
+    curve.validate()?;
+
+    let master = curve.rgb.as_deref().map(build_lut);
+    let luts: Vec<[u8; 256]> = [&curve.red, &curve.green, &curve.blue]
+        .into_iter()
+        .map(|points| {
+            let channel = points.as_deref().map(build_lut);
+            std::array::from_fn(|i| {
+                let v = channel.map_or(i as u8, |lut| lut[i]);
+                master.map_or(v, |lut| lut[v as usize])
+            })
+        })
+        .collect();
+
+    let mut rgba = img.to_rgba8();
+    for pixel in rgba.pixels_mut() {
+        for (c, lut) in luts.iter().enumerate() {
+            pixel[c] = lut[pixel[c] as usize];
+        }
+    }
+
+    Ok(if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+    })
+}
+
+/// Largest correction `auto_white_balance` applies to any channel, either
+/// way, so a scene that really is mostly one color is not turned gray.
+const MAX_WB_GAIN: f32 = 2.0;
+
+/// Gray-world white balance: scale red and blue so their means match the
+/// green mean, on the assumption that a scene averages out to neutral.
+/// Means come from per-channel histograms of the pixels with no clipped
+/// channel, since clipped highlights and crushed shadows carry no color
+/// information. Green is the reference because it drives most of perceived
+/// brightness. Alpha is left untouched.
+pub fn auto_white_balance(img: &DynamicImage) -> DynamicImage {
+    let mut rgba = img.to_rgba8();
+
+    let mut histograms = [[0u64; 256]; 3];
+    for pixel in rgba.pixels() {
+        if pixel.0[..3].iter().all(|&v| v > 0 && v < 255) {
+            for (c, histogram) in histograms.iter_mut().enumerate() {
+                histogram[pixel[c] as usize] += 1;
+            }
+        }
+    }
+    let count: u64 = histograms[1].iter().sum();
+    if count == 0 {
+        return img.clone();
+    }
+    let means = histograms.map(|histogram| {
+        let sum: u64 = histogram.iter().enumerate().map(|(v, &n)| v as u64 * n).sum();
+        sum as f32 / count as f32
+    });
+
+    let luts = means.map(|mean| {
+        let gain = (means[1] / mean).clamp(1.0 / MAX_WB_GAIN, MAX_WB_GAIN);
+        let lut: [u8; 256] =
+            std::array::from_fn(|i| (i as f32 * gain).round().clamp(0.0, 255.0) as u8);
+        lut
+    });
+    for pixel in rgba.pixels_mut() {
+        for (c, lut) in luts.iter().enumerate() {
+            pixel[c] = lut[pixel[c] as usize];
+        }
+    }
+
+    if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+    }
+}
+
+fn build_lut(points: &[[u8; 2]]) -> [u8; 256] {
+    let mut sorted = points.to_vec();
+    sorted.sort_by_key(|p| p[0]);
+    let (first, last) = (sorted[0], sorted[sorted.len() - 1]);
+
+    std::array::from_fn(|i| {
+        let x = i as u8;
+        if x <= first[0] {
+            return first[1];
+        }
+        if x >= last[0] {
+            return last[1];
+        }
+        let segment = sorted.windows(2).find(|w| x <= w[1][0]).unwrap();
+        let ([x0, y0], [x1, y1]) = (segment[0], segment[1]);
+        let t = (x - x0) as f32 / (x1 - x0) as f32;
+        (y0 as f32 + t * (y1 as f32 - y0 as f32)).round() as u8
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dither_is_repeatable() {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(37, 23, |x, y| {
+            image::Rgb([(x * 7) as u8, (y * 11) as u8, ((x * y) % 256) as u8])
+        }));
+        for method in DITHER_METHODS {
+            let first = dither(&img, method).unwrap().into_raw();
+            for _ in 0..3 {
+                assert_eq!(dither(&img, method).unwrap().into_raw(), first, "{}", method);
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+
+use crate::metadata;
+
+/// Operators accepted by the `tone_map` export option. `none` exports the
+/// HDR code values as if they were sRGB.
+pub const TONE_MAPS: [&str; 3] = ["reinhard", "aces", "none"];
+
+/// Operator used when HDR input is detected and `tone_map` is unset.
+pub const DEFAULT_TONE_MAP: &str = "reinhard";
+
+/// SDR reference white in nits (ITU-R BT.2408). HDR luminance is divided by
+/// this, so diffuse white in the HDR image lands at 1.0.
+const REFERENCE_WHITE_NITS: f32 = 203.0;
+/// Peak luminance assumed for PQ content and the nominal HLG display.
+const PEAK_NITS: f32 = 1000.0;
+/// BT.2100 HLG system gamma for a 1000 nit display.
+const HLG_SYSTEM_GAMMA: f32 = 1.2;
+
+/// HDR transfer characteristics, as coded in H.273.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    /// SMPTE ST 2084 perceptual quantizer, code 16.
+    Pq,
+    /// ARIB STD-B67 hybrid log-gamma, code 18.
+    Hlg,
+}
+
+/// HDR signalling read from the source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HdrInfo {
+    pub transfer: Transfer,
+    /// BT.2020 primaries (H.273 code 9); otherwise BT.709 is assumed.
+    pub bt2020: bool,
+}
+
+/// HDR transfer signalled by `bytes`, currently the PNG `cICP` chunk.
+/// JPEG has no such signal, and HEIF/AVIF `nclx` boxes are not read.
+pub fn detect(bytes: &[u8]) -> Option<HdrInfo> {
+    let cicp = metadata::png_chunks(bytes, b"cICP").into_iter().next()?;
+    let (&primaries, &transfer) = (cicp.first()?, cicp.get(1)?);
+    let transfer = match transfer {
+        16 => Transfer::Pq,
+        18 => Transfer::Hlg,
+        _ => return None,
+    };
+    Some(HdrInfo {
+        transfer,
+        bt2020: primaries == 9,
+    })
+}
+
+/// Map HDR pixels to 8-bit sRGB with `operator` (one of `TONE_MAPS`).
+///
+/// Code values are linearized with the inverse PQ EOTF or HLG OETF plus the
+/// HLG OOTF, converted from BT.2020 to BT.709 primaries when needed, and
+/// scaled so reference white is 1.0. Then:
+///
+/// - `reinhard`: extended Reinhard on luminance, `L (1 + L / W²) / (1 + L)`
+///   with white point `W` at the assumed 1000 nit peak; hue and saturation
+///   are kept by scaling all channels by the same factor.
+/// - `aces`: Narkowicz's per-channel fit of the ACES filmic curve, punchier
+///   but shifting very bright colors toward white.
+///
+/// Out-of-gamut results are clipped. Alpha is kept.
+pub fn tone_map(img: &DynamicImage, info: HdrInfo, operator: &str) -> Result<DynamicImage, String> {
+    let map: fn([f32; 3]) -> [f32; 3] = match operator {
+        "reinhard" => reinhard,
+        "aces" => aces,
+        "none" => return Ok(img.clone()),
+        other => return Err(format!("Unsupported tone map: {}", other)),
+    };
+
+    let src: Rgba32FImage = img.to_rgba32f();
+    let out = RgbaImage::from_fn(src.width(), src.height(), |x, y| {
+        let [r, g, b, a] = src.get_pixel(x, y).0;
+        let mut rgb = linearize([r, g, b], info);
+        if info.bt2020 {
+            rgb = bt2020_to_bt709(rgb);
+        }
+        let [r, g, b] = map(rgb).map(|c| to_u8(linear_to_srgb(c)));
+        image::Rgba([r, g, b, to_u8(a)])
+    });
+
+    Ok(if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(out)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(out).to_rgb8())
+    })
+}
+
+/// Code values to linear light relative to reference white.
+fn linearize(rgb: [f32; 3], info: HdrInfo) -> [f32; 3] {
+    match info.transfer {
+        Transfer::Pq => rgb.map(|e| pq_eotf(e) * 10000.0 / REFERENCE_WHITE_NITS),
+        Transfer::Hlg => {
+            let scene = rgb.map(hlg_inverse_oetf);
+            // The OOTF scales by scene luminance to the power gamma - 1.
+            let [r, g, b] = scene;
+            let ys = if info.bt2020 {
+                0.2627 * r + 0.6780 * g + 0.0593 * b
+            } else {
+                luminance(scene)
+            };
+            let gain = PEAK_NITS * ys.max(0.0).powf(HLG_SYSTEM_GAMMA - 1.0) / REFERENCE_WHITE_NITS;
+            scene.map(|e| e * gain)
+        }
+    }
+}
+
+/// ST 2084 EOTF: code value to display luminance as a fraction of 10000 nits.
+fn pq_eotf(e: f32) -> f32 {
+    const M1: f32 = 2610.0 / 16384.0;
+    const M2: f32 = 2523.0 / 4096.0 * 128.0;
+    const C1: f32 = 3424.0 / 4096.0;
+    const C2: f32 = 2413.0 / 4096.0 * 32.0;
+    const C3: f32 = 2392.0 / 4096.0 * 32.0;
+    let p = e.clamp(0.0, 1.0).powf(1.0 / M2);
+    ((p - C1).max(0.0) / (C2 - C3 * p)).powf(1.0 / M1)
+}
+
+/// BT.2100 HLG inverse OETF: code value to normalized scene light.
+fn hlg_inverse_oetf(e: f32) -> f32 {
+    const A: f32 = 0.178_832_77;
+    const B: f32 = 1.0 - 4.0 * A;
+    const C: f32 = 0.559_910_7;
+    let e = e.clamp(0.0, 1.0);
+    if e <= 0.5 {
+        e * e / 3.0
+    } else {
+        (((e - C) / A).exp() + B) / 12.0
+    }
+}
+
+fn bt2020_to_bt709([r, g, b]: [f32; 3]) -> [f32; 3] {
+    [
+        1.6605 * r - 0.5876 * g - 0.0728 * b,
+        -0.1246 * r + 1.1329 * g - 0.0083 * b,
+        -0.0182 * r - 0.1006 * g + 1.1187 * b,
+    ]
+}
+
+fn luminance([r, g, b]: [f32; 3]) -> f32 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+fn reinhard(rgb: [f32; 3]) -> [f32; 3] {
+    let white = PEAK_NITS / REFERENCE_WHITE_NITS;
+    let l = luminance(rgb);
+    if l <= 0.0 {
+        return [0.0; 3];
+    }
+    let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+    rgb.map(|c| c * mapped / l)
+}
+
+fn aces(rgb: [f32; 3]) -> [f32; 3] {
+    rgb.map(|x| {
+        let x = x.max(0.0) * 0.6;
+        (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+    })
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.003_130_8 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c * 255.0).round().clamp(0.0, 255.0) as u8
+}
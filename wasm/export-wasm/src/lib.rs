@@ -1,14 +1,34 @@
+mod animation;
+mod atlas;
+mod decode;
+mod filters;
+mod hdr;
+mod metadata;
+#[path = "../../shared/presets.rs"]
+mod presets;
+mod provenance;
+mod quality;
+mod resize;
 mod utils;
 
 use image::{
-    codecs::jpeg::JpegEncoder, codecs::png::PngEncoder, codecs::webp::WebPEncoder,
-    imageops::FilterType, DynamicImage, ExtendedColorType, ImageEncoder,
+    codecs::jpeg::JpegEncoder,
+    codecs::png::{CompressionType, FilterType as PngFilterType, PngEncoder},
+    codecs::qoi::QoiEncoder,
+    codecs::webp::WebPEncoder,
+    imageops::FilterType, DynamicImage, ExtendedColorType, GenericImageView, GrayImage,
+    ImageEncoder,
+    metadata::Orientation,
 };
 use js_sys::{Array, Uint8Array};
 use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use wasm_bindgen::prelude::*;
 use web_sys::{Blob, BlobPropertyBag};
 
+use crate::utils::Deadline;
+
 // When the `wee_alloc` feature is enabled, use `wee_alloc` as the global
 // allocator.
 #[cfg(feature = "wee_alloc")]
@@ -38,28 +58,375 @@ macro_rules! console_error {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct ExportOptions {
-    pub format: String, // "jpeg", "png", "webp", "original"
-    pub quality: f32,   // 0.1 to 1.0 for lossy formats
+    pub format: String, // "jpeg", "png", "webp", "qoi", "auto", "original"
+    #[serde(default)]
+    pub quality: Option<f32>, // 0.1 to 1.0 for lossy formats; wins over quality_preset
     pub max_width: Option<u32>,
     pub max_height: Option<u32>,
     pub filename: Option<String>,
+    pub smart_quality: Option<bool>, // JPEG only: derive quality per image from a target SSIM
+    pub png_palette: Option<bool>,   // PNG only: indexed color, median-cut to 256 colors when needed
+    pub auto_formats: Option<Vec<String>>, // candidates tried by "auto", default jpeg + webp
+    pub quality_jpeg: Option<f32>, // overrides `quality` for JPEG output
+    pub quality_webp: Option<f32>, // must be 1.0: WebP output is lossless only, see webp_near_lossless
+    pub upscale_filter: Option<String>, // filter when enlarging; downscales keep Lanczos3
+    pub apply_kernel: Option<Vec<f32>>, // row-major convolution weights, see filters::convolve
+    pub kernel_size: Option<u32>,       // 3 or 5; kernel length must be kernel_size²
+    pub never_upscale: Option<bool>,    // clamp output to the source dimensions
+    // EXIF orientation (1-8) applied before resizing:
+    //   1 none            2 flip horizontal      3 rotate 180     4 flip vertical
+    //   5 transpose       6 rotate 90 cw         7 transverse     8 rotate 270 cw
+    // where transpose = rotate 90 cw + flip horizontal and
+    // transverse = rotate 270 cw + flip horizontal.
+    //
+    // Orientation sources, highest precedence first:
+    //   1. orientation_override, which replaces the embedded EXIF value.
+    //   2. HEIF container rotation/mirroring (irot/imir), always applied by
+    //      libheif at decode. EXIF Orientation in HEIF is ignored, as the
+    //      HEIF spec requires, so the two can never both apply.
+    //   3. The embedded EXIF Orientation, only with apply_exif_orientation.
+    // Without either option, JPEG/PNG/WebP/TIFF pixels are kept as stored.
+    pub orientation_override: Option<u8>,
+    pub apply_exif_orientation: Option<bool>, // rotate by the source's EXIF Orientation, see above
+    pub quality_preset: Option<String>, // "low", "medium", "high", "max", see quality::Preset
+    pub dither: Option<String>, // PNG only: 1-bit output, "floyd-steinberg", "ordered", "threshold"
+    pub curve: Option<filters::CurvePoints>, // tone curve applied after resizing and the kernel
+    pub embed_thumbnail: Option<u32>, // JPEG only: max edge of an EXIF preview, up to 320
+    pub time_budget_ms: Option<u32>, // cut quality searches and "auto" candidates short; fast PNG compression
+    pub embed_provenance: Option<provenance::ProvenanceOptions>, // signature in XMP and/or pixel LSBs
+    pub max_bytes: Option<u32>,  // JPEG only: search quality for output of at most this size
+    pub quality_min: Option<f32>, // floor of the `max_bytes` search, default 0.1
+    pub quality_max: Option<f32>, // ceiling of the `max_bytes` search, default the JPEG quality
+    pub png_minimal: Option<bool>, // PNG only: best compression and critical chunks only
+    pub keep_smaller: Option<bool>, // return the input (with its metadata) when re-encoding would not shrink it
+    pub auto_white_balance: Option<bool>, // gray-world correction before the curve, see filters::auto_white_balance
+    pub clamp_overshoot: Option<bool>, // clip resize ringing to the local source range, see resize::clamp_overshoot
+    pub inject_metadata: Option<HashMap<String, String>>, // JPEG/PNG EXIF text, keys from metadata::INJECTABLE_TAGS
+    pub pad_to_exact: Option<bool>, // letterbox to exactly max_width x max_height (both required)
+    pub background_color: Option<[u8; 4]>, // RGBA fill for `pad_to_exact`, default opaque white
+    // WebP only: 0-100 near-lossless preprocessing level, 100 = exact. WebP
+    // output is always lossless here, so this is the only way to trade
+    // fidelity for size; see filters::near_lossless.
+    pub webp_near_lossless: Option<u8>,
+    pub memory_budget: Option<u32>, // max bytes of the RGBA output buffer; larger outputs shrink to fit, a larger pad_to_exact canvas is an error
+    pub adaptive_quality: Option<bool>, // JPEG only: raise quality for downscaled output, see quality::adaptive_jpeg_quality
+    pub dpi: Option<f32>, // JPEG/PNG: resolution tag; with a physical size also the pixels per inch
+    pub physical_width_inches: Option<f32>,  // print width; needs `dpi`, replaces max_width
+    pub physical_height_inches: Option<f32>, // print height; needs `dpi`, replaces max_height
+    #[serde(default, with = "serde_bytes")]
+    pub icc_profile: Option<Vec<u8>>, // JPEG/PNG/WebP: ICC profile to embed, e.g. Display P3
+    // Operator for HDR (PQ/HLG) sources: "reinhard" (default), "aces" or
+    // "none". Only applies when the source signals HDR; see hdr::tone_map.
+    pub tone_map: Option<String>,
+}
+
+/// Largest output side accepted from `max_width`, `max_height` or a
+/// physical print size.
+const MAX_DIMENSION: u32 = 16384;
+
+/// Optional width and height bounds in pixels.
+type Bounds = (Option<u32>, Option<u32>);
+
+/// Largest `embed_thumbnail` accepted; bigger previews risk overflowing the
+/// 64 KB EXIF segment.
+const MAX_EMBEDDED_THUMBNAIL: u32 = 320;
+/// JPEG quality of the embedded EXIF preview.
+const EMBEDDED_THUMBNAIL_QUALITY: f32 = 0.75;
+
+/// JPEG quality of `quick_preview`.
+const PREVIEW_QUALITY: f32 = 0.6;
+
+/// Fill around the image for `pad_to_exact` without a `background_color`.
+const DEFAULT_PAD_COLOR: [u8; 4] = [255, 255, 255, 255];
+
+/// Quality used when neither `quality` nor `quality_preset` is given.
+const DEFAULT_QUALITY: f32 = 0.9;
+
+impl ExportOptions {
+    fn preset(&self) -> Option<quality::Preset> {
+        self.quality_preset.as_deref().and_then(quality::preset)
+    }
+
+    /// JPEG quality: `quality_jpeg`, then `quality`, then the preset.
+    fn jpeg_quality(&self) -> f32 {
+        self.quality_jpeg
+            .or(self.quality)
+            .or_else(|| self.preset().map(|p| p.jpeg_quality))
+            .unwrap_or(DEFAULT_QUALITY)
+    }
+
+    /// WebP quality: presets leave WebP lossless.
+    fn webp_quality(&self) -> f32 {
+        self.quality_webp.or(self.quality).unwrap_or(1.0)
+    }
+
+    /// The WebP encoder is lossless only, so `quality_webp` can only be 1.0.
+    fn check_webp_quality(&self) -> Result<(), String> {
+        match self.quality_webp {
+            Some(quality) if quality != 1.0 => Err(format!(
+                "quality_webp must be 1.0, got {}: WebP output is lossless only; \
+                 use webp_near_lossless to trade fidelity for size",
+                quality
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether any option alters pixels beyond resizing, which rules out
+    /// handing back the input under `keep_smaller`.
+    fn edits_pixels(&self) -> bool {
+        self.apply_kernel.is_some()
+            || self.curve.is_some()
+            || self.auto_white_balance.unwrap_or(false)
+            || self.orientation_override.is_some()
+            || self.apply_exif_orientation.unwrap_or(false)
+            || self.dither.is_some()
+            || self.embed_provenance.is_some()
+            || self.webp_near_lossless.is_some_and(|level| level < 100)
+    }
+
+    /// Whether PNG/WebP output keeps the final pixels bit-exact, which LSB
+    /// provenance relies on.
+    fn exact_pixels(&self) -> bool {
+        self.dither.is_none() && !self.webp_near_lossless.is_some_and(|level| level < 100)
+    }
+
+    /// Whether `png_palette` may quantize: not when LSB provenance has to
+    /// survive in the final pixels.
+    fn allows_quantization(&self) -> bool {
+        !self
+            .embed_provenance
+            .as_ref()
+            .is_some_and(|prov| prov.lsb.unwrap_or(false))
+    }
+
+    fn has_print_size(&self) -> bool {
+        self.physical_width_inches.is_some() || self.physical_height_inches.is_some()
+    }
+
+    /// Pixel bounds for `physical_width_inches` / `physical_height_inches`
+    /// at `dpi`, or `None` when no physical size is set.
+    fn print_size(&self) -> Result<Option<Bounds>, String> {
+        if !self.has_print_size() {
+            return Ok(None);
+        }
+        let dpi = self
+            .dpi
+            .filter(|dpi| dpi.is_finite() && *dpi > 0.0)
+            .ok_or("A physical size needs a positive dpi")?;
+        let pixels = |inches: Option<f32>| -> Result<Option<u32>, String> {
+            let Some(inches) = inches else {
+                return Ok(None);
+            };
+            let pixels = (inches * dpi).round();
+            if !inches.is_finite() || !(1.0..=MAX_DIMENSION as f32).contains(&pixels) {
+                return Err(format!(
+                    "{} inches at {} dpi is outside 1-{} pixels",
+                    inches, dpi, MAX_DIMENSION
+                ));
+            }
+            Ok(Some(pixels as u32))
+        };
+        Ok(Some((
+            pixels(self.physical_width_inches)?,
+            pixels(self.physical_height_inches)?,
+        )))
+    }
+
+    /// `inject_metadata` as EXIF (tag, value) pairs; unknown keys are an
+    /// error rather than silently dropped.
+    fn exif_fields(&self) -> Result<Vec<(u16, String)>, String> {
+        let Some(ref entries) = self.inject_metadata else {
+            return Ok(Vec::new());
+        };
+        entries
+            .iter()
+            .map(|(key, value)| {
+                let tag = metadata::injectable_tag(key)
+                    .ok_or_else(|| format!("Unsupported metadata field: {}", key))?;
+                if value.contains('\0') {
+                    return Err(format!("Metadata field {} contains a NUL byte", key));
+                }
+                Ok((tag, value.clone()))
+            })
+            .collect()
+    }
+
+    /// PNG effort: `png_minimal` asks for the smallest file, then a time
+    /// budget picks the fastest level (about 2-4x faster than the default
+    /// and best levels on a native 12 MP test, for a larger file), then the
+    /// preset. JPEG and lossless WebP have no effort setting in `image`.
+    fn png_compression(&self) -> CompressionType {
+        if self.png_minimal.unwrap_or(false) {
+            return CompressionType::Best;
+        }
+        if self.time_budget_ms.is_some() {
+            return CompressionType::Fast;
+        }
+        self.preset()
+            .map(|p| p.png_compression)
+            .unwrap_or(CompressionType::Default)
+    }
+}
+
+/// The `png` crate's level for an `image` compression setting, for the
+/// encoders that write PNG through `png` directly.
+fn png_crate_compression(compression: CompressionType) -> png::Compression {
+    match compression {
+        CompressionType::Fast => png::Compression::Fast,
+        CompressionType::Best => png::Compression::High,
+        _ => png::Compression::Balanced,
+    }
+}
+
+/// Formats `"auto"` compares when `auto_formats` is not given.
+const DEFAULT_AUTO_FORMATS: [&str; 2] = ["jpeg", "webp"];
+
+fn normalize_format(format: &str) -> &str {
+    match format {
+        "jpg" => "jpeg",
+        other => other,
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ExportResult {
     pub success: bool,
-    pub data: Option<Vec<u8>>,
+    #[serde(with = "serde_bytes")]
+    pub data: Option<Vec<u8>>, // Uint8Array, transferable; see wasm/README.md
     pub filename: Option<String>,
     pub error: Option<String>,
     pub width: u32,
     pub height: u32,
+    pub warning: Option<String>,
+    pub memory: Option<MemoryStats>,
+    pub format: Option<String>, // encoded format, resolved when the request was "auto"
+    pub budget_met: Option<bool>, // set when `time_budget_ms` was given
+    pub requested_width: Option<u32>,  // `max_width` as passed in
+    pub requested_height: Option<u32>, // `max_height` as passed in
+    pub was_resized: bool,
+    pub was_cropped: bool, // set by `export_crops` only
+    pub quality: Option<f32>,       // JPEG quality used, after smart quality or `max_bytes`
+    pub max_bytes_met: Option<bool>, // set when `max_bytes` was given
+    pub kept_original: Option<bool>, // set when `keep_smaller` was given
+    pub downscaled_for_memory: Option<bool>, // set when `memory_budget` was given
+}
+
+/// Side results of encoding one format, copied into `ExportResult`.
+#[derive(Debug, Default)]
+struct EncodeReport {
+    warning: Option<String>,
+    quality: Option<f32>,
+    max_bytes_met: Option<bool>,
+}
+
+/// Buffer sizes touched by an export, derived from `width * height * channels`
+/// rather than real heap tracking.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MemoryStats {
+    pub input_bytes: u64,   // encoded bytes passed to `load_from_bytes`
+    pub decoded_bytes: u64, // pixel buffer held by the processor
+    pub output_bytes: u64,  // encoded export
+    pub peak_estimate: u64, // upper bound of buffers alive at the same time
+}
+
+/// Encoded sizes of the loaded image per format, from `compare_encodings`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncodingComparison {
+    pub jpeg_bytes: u32,
+    pub webp_lossy_bytes: Option<u32>, // null: no lossy WebP encoder in this build
+    pub webp_lossless_bytes: u32,
+    pub png_bytes: u32,
+}
+
+/// One region for `export_crops`, in pixels of the loaded image.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CropRequest {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    pub max_dimension: Option<u32>, // shrink the crop to fit this, never enlarge
+}
+
+/// Small JPEG of the loaded image from `quick_preview`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Preview {
+    pub width: u32,
+    pub height: u32,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// Outcome of one input in a batch call; exactly one of `result`/`error` is set.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BatchItemResult {
+    pub index: u32,
+    pub ok: bool,
+    pub result: Option<ExportResult>,
+    pub error: Option<String>,
+}
+
+impl BatchItemResult {
+    fn new(index: u32, outcome: Result<ExportResult, String>) -> BatchItemResult {
+        match outcome {
+            Ok(result) => BatchItemResult {
+                index,
+                ok: true,
+                result: Some(result),
+                error: None,
+            },
+            Err(e) => BatchItemResult {
+                index,
+                ok: false,
+                result: None,
+                error: Some(e),
+            },
+        }
+    }
+}
+
+impl ExportResult {
+    fn failure(error: String) -> ExportResult {
+        ExportResult {
+            success: false,
+            data: None,
+            filename: None,
+            error: Some(error),
+            width: 0,
+            height: 0,
+            warning: None,
+            memory: None,
+            format: None,
+            budget_met: None,
+            requested_width: None,
+            requested_height: None,
+            was_resized: false,
+            was_cropped: false,
+            quality: None,
+            max_bytes_met: None,
+            kept_original: None,
+            downscaled_for_memory: None,
+        }
+    }
 }
 
 #[wasm_bindgen]
 pub struct ImageProcessor {
     image: Option<DynamicImage>,
+    source_bytes: usize,
+    full_size: Option<(u32, u32)>, // source size when `image` was decoded reduced
+    source: Option<Vec<u8>>,       // encoded input, kept only for `keep_smaller`
+    exif_orientation: Option<u8>,  // source EXIF Orientation, for `apply_exif_orientation`
+    hdr: Option<hdr::HdrInfo>,     // HDR transfer signalled by the source, see `tone_map`
+}
+
+impl Default for ImageProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[wasm_bindgen]
@@ -69,20 +436,49 @@ impl ImageProcessor {
         utils::set_panic_hook();
         console_log!("ImageProcessor initialized");
 
-        ImageProcessor { image: None }
+        ImageProcessor {
+            image: None,
+            source_bytes: 0,
+            full_size: None,
+            source: None,
+            exif_orientation: None,
+            hdr: None,
+        }
     }
 
-    /// Load image from byte array
+    /// Load image from byte array. With `max_decode_dimension` the stored
+    /// image is reduced to fit it at decode time (see
+    /// `decode::decode_image_bounded`); `get_full_dimensions` still reports
+    /// the source size.
+    ///
+    /// The encoded input is only kept when `keep_source` is set, which
+    /// `keep_smaller` exports need; otherwise they report
+    /// `kept_original: false`.
     #[wasm_bindgen]
-    pub fn load_from_bytes(&mut self, bytes: &[u8]) -> bool {
-        match image::load_from_memory(bytes) {
-            Ok(img) => {
+    pub fn load_from_bytes(
+        &mut self,
+        bytes: &[u8],
+        max_decode_dimension: Option<u32>,
+        keep_source: Option<bool>,
+    ) -> bool {
+        let decoded = match max_decode_dimension {
+            Some(max) => decode::decode_image_bounded(bytes, max)
+                .map(|(img, full_size)| (img, Some(full_size))),
+            None => decode::decode_image(bytes).map(|img| (img, None)),
+        };
+        match decoded {
+            Ok((img, full_size)) => {
                 console_log!(
                     "Image loaded successfully: {}x{}",
                     img.width(),
                     img.height()
                 );
+                self.full_size = full_size.filter(|&size| size != (img.width(), img.height()));
                 self.image = Some(img);
+                self.source_bytes = bytes.len();
+                self.source = keep_source.unwrap_or(false).then(|| bytes.to_vec());
+                self.exif_orientation = metadata::exif_orientation(bytes);
+                self.hdr = hdr::detect(bytes);
                 true
             }
             Err(e) => {
@@ -92,6 +488,39 @@ impl ImageProcessor {
         }
     }
 
+    /// Load from a base64 `data:` URL such as canvas `toDataURL()` output.
+    /// The media type is not checked; the image format is sniffed from the
+    /// decoded bytes as with `load_from_bytes`.
+    #[wasm_bindgen]
+    pub fn load_from_data_url(&mut self, data_url: &str) -> bool {
+        match decode_data_url(data_url) {
+            Ok(bytes) => self.load_from_bytes(&bytes, None, None),
+            Err(e) => {
+                console_error!("Failed to load data URL: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Dimensions of the source before any decode-time reduction, as
+    /// `[width, height]`.
+    #[wasm_bindgen]
+    pub fn get_full_dimensions(&self) -> Option<Array> {
+        let (width, height) = self
+            .full_size
+            .or_else(|| self.image.as_ref().map(|img| (img.width(), img.height())))?;
+        let dimensions = Array::new();
+        dimensions.set(0, JsValue::from(width));
+        dimensions.set(1, JsValue::from(height));
+        Some(dimensions)
+    }
+
+    /// Whether the loaded image was reduced at decode time.
+    #[wasm_bindgen]
+    pub fn is_reduced(&self) -> bool {
+        self.full_size.is_some()
+    }
+
     /// Get image dimensions
     #[wasm_bindgen]
     pub fn get_dimensions(&self) -> Option<Array> {
@@ -112,14 +541,10 @@ impl ImageProcessor {
             Ok(opts) => opts,
             Err(e) => {
                 console_error!("Failed to parse export options: {}", e);
-                return serde_wasm_bindgen::to_value(&ExportResult {
-                    success: false,
-                    data: None,
-                    filename: None,
-                    error: Some(format!("Invalid options: {}", e)),
-                    width: 0,
-                    height: 0,
-                })
+                return serde_wasm_bindgen::to_value(&ExportResult::failure(format!(
+                    "Invalid options: {}",
+                    e
+                )))
                 .unwrap();
             }
         };
@@ -135,29 +560,180 @@ impl ImageProcessor {
                 }
                 Err(e) => {
                     console_error!("Image export failed: {}", e);
-                    serde_wasm_bindgen::to_value(&ExportResult {
-                        success: false,
-                        data: None,
-                        filename: None,
-                        error: Some(e),
-                        width: 0,
-                        height: 0,
-                    })
-                    .unwrap()
+                    serde_wasm_bindgen::to_value(&ExportResult::failure(e)).unwrap()
                 }
             }
         } else {
             console_error!("No image loaded");
-            serde_wasm_bindgen::to_value(&ExportResult {
-                success: false,
-                data: None,
-                filename: None,
-                error: Some("No image loaded".to_string()),
-                width: 0,
-                height: 0,
-            })
-            .unwrap()
+            serde_wasm_bindgen::to_value(&ExportResult::failure("No image loaded".to_string()))
+                .unwrap()
+        }
+    }
+
+    /// Export the alpha channel of the loaded image as a grayscale JPEG or
+    /// PNG (opaque = white), as an `ExportResult`. The counterpart of
+    /// `apply_mask`, which turns such a mask back into alpha. Images without
+    /// an alpha channel fail rather than yield an all-white mask.
+    #[wasm_bindgen]
+    pub fn export_alpha_mask(&self, format: &str, quality: f32) -> JsValue {
+        let result = match self.image.as_ref() {
+            None => Err("No image loaded".to_string()),
+            Some(img) if !img.color().has_alpha() => Err("Image has no alpha channel".to_string()),
+            Some(img) => match format.to_lowercase().as_str() {
+                format @ ("jpeg" | "jpg" | "png") => {
+                    let rgba = img.to_rgba8();
+                    let mask = GrayImage::from_fn(img.width(), img.height(), |x, y| {
+                        image::Luma([rgba.get_pixel(x, y)[3]])
+                    });
+                    let options = ExportOptions {
+                        format: format.to_string(),
+                        quality: Some(quality),
+                        ..Default::default()
+                    };
+                    self.process_image(DynamicImage::ImageLuma8(mask), &options)
+                }
+                other => Err(format!("Alpha masks export as jpeg or png, got {}", other)),
+            },
+        };
+
+        let result = result.unwrap_or_else(|e| {
+            console_error!("Alpha mask export failed: {}", e);
+            ExportResult::failure(e)
+        });
+        serde_wasm_bindgen::to_value(&result).unwrap()
+    }
+
+    /// Export into a caller-owned buffer instead of returning an
+    /// `ExportResult`, skipping the serde conversion of the encoded bytes.
+    /// Returns the encoded length, or -1 when `out` is too small (nothing is
+    /// written then). Errors are thrown.
+    #[wasm_bindgen]
+    pub fn export_into(&self, options_js: &JsValue, out: &mut [u8]) -> Result<i32, JsError> {
+        let options: ExportOptions = serde_wasm_bindgen::from_value(options_js.clone())
+            .map_err(|e| JsError::new(&format!("Invalid options: {}", e)))?;
+        let img = self
+            .image
+            .as_ref()
+            .ok_or_else(|| JsError::new("No image loaded"))?;
+
+        let result = self
+            .process_image(img.clone(), &options)
+            .map_err(|e| JsError::new(&e))?;
+        let data = result.data.unwrap_or_default();
+        match out.get_mut(..data.len()) {
+            Some(target) => {
+                target.copy_from_slice(&data);
+                i32::try_from(data.len()).map_err(|_| JsError::new("Output exceeds 2 GiB"))
+            }
+            None => Ok(-1),
+        }
+    }
+
+    /// `{ width, height, data }` with a JPEG of the loaded image fitting
+    /// `max_dimension`, for showing while export options are still being
+    /// chosen. Uses the fast `thumbnail` sampling thumbnail-wasm uses and
+    /// ignores every export option, so it is not a preview of the final
+    /// export's quality.
+    #[wasm_bindgen]
+    pub fn quick_preview(&self, max_dimension: u32) -> Result<JsValue, JsError> {
+        if max_dimension == 0 {
+            return Err(JsError::new("max_dimension must be non-zero"));
         }
+        let img = self
+            .image
+            .as_ref()
+            .ok_or_else(|| JsError::new("No image loaded"))?;
+
+        let small = if img.width() > max_dimension || img.height() > max_dimension {
+            Cow::Owned(img.thumbnail(max_dimension, max_dimension))
+        } else {
+            Cow::Borrowed(img)
+        };
+        let preview = Preview {
+            width: small.width(),
+            height: small.height(),
+            data: self
+                .encode_jpeg(&small, PREVIEW_QUALITY)
+                .map_err(|e| JsError::new(&e))?,
+        };
+        serde_wasm_bindgen::to_value(&preview).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Export several regions of the loaded image, e.g. 1:1, 16:9 and 9:16
+    /// social cards, without decoding it again. `crops_js` is an array of
+    /// `{ x, y, w, h, max_dimension }` in pixels of the loaded image (see
+    /// `get_dimensions`). Returns one `ExportResult` per crop, in order; a
+    /// crop outside the image fails on its own.
+    #[wasm_bindgen]
+    pub fn export_crops(
+        &self,
+        crops_js: &JsValue,
+        format: &str,
+        quality: f32,
+    ) -> Result<JsValue, JsError> {
+        let crops: Vec<CropRequest> = serde_wasm_bindgen::from_value(crops_js.clone())
+            .map_err(|e| JsError::new(&format!("Invalid crops: {}", e)))?;
+        let img = self
+            .image
+            .as_ref()
+            .ok_or_else(|| JsError::new("No image loaded"))?;
+
+        let results: Vec<ExportResult> = crops
+            .iter()
+            .map(|crop| {
+                let fits = crop.w > 0
+                    && crop.h > 0
+                    && crop.x as u64 + crop.w as u64 <= img.width() as u64
+                    && crop.y as u64 + crop.h as u64 <= img.height() as u64;
+                if !fits {
+                    return ExportResult::failure(format!(
+                        "Crop {}x{} at {},{} is outside the {}x{} image",
+                        crop.w,
+                        crop.h,
+                        crop.x,
+                        crop.y,
+                        img.width(),
+                        img.height()
+                    ));
+                }
+
+                let options = ExportOptions {
+                    format: format.to_lowercase(),
+                    quality: Some(quality),
+                    max_width: crop.max_dimension,
+                    max_height: crop.max_dimension,
+                    ..Default::default()
+                };
+                let region = img.crop_imm(crop.x, crop.y, crop.w, crop.h);
+                match self.process_image(region, &options) {
+                    Ok(mut result) => {
+                        result.was_cropped = true;
+                        result
+                    }
+                    Err(e) => ExportResult::failure(e),
+                }
+            })
+            .collect();
+        serde_wasm_bindgen::to_value(&results).map_err(|e| JsError::new(&e.to_string()))
+    }
+
+    /// Encode the loaded image with every available encoder at `quality` and
+    /// report the resulting sizes.
+    #[wasm_bindgen]
+    pub fn compare_encodings(&self, quality: f32) -> Result<JsValue, JsError> {
+        let img = self
+            .image
+            .as_ref()
+            .ok_or_else(|| JsError::new("No image loaded"))?;
+
+        let comparison = EncodingComparison {
+            jpeg_bytes: self.encode_jpeg(img, quality).map_err(|e| JsError::new(&e))?.len() as u32,
+            webp_lossy_bytes: None,
+            webp_lossless_bytes: self.encode_webp(img, 1.0).map_err(|e| JsError::new(&e))?.len()
+                as u32,
+            png_bytes: self.encode_png(img, CompressionType::Default).map_err(|e| JsError::new(&e))?.len() as u32,
+        };
+        serde_wasm_bindgen::to_value(&comparison).map_err(|e| JsError::new(&e.to_string()))
     }
 
     fn process_image(
@@ -165,42 +741,264 @@ impl ImageProcessor {
         mut img: DynamicImage,
         options: &ExportOptions,
     ) -> Result<ExportResult, String> {
+        let deadline = Deadline::after_ms(options.time_budget_ms);
+        let decoded_bytes = img.as_bytes().len() as u64;
+        let exif_fields = options.exif_fields()?;
+        options.check_webp_quality()?;
+
+        // Tone map first: every later step works on SDR values.
+        let tone_map = options.tone_map.as_deref().unwrap_or(hdr::DEFAULT_TONE_MAP);
+        if !hdr::TONE_MAPS.contains(&tone_map) {
+            return Err(format!("Unsupported tone map: {}", tone_map));
+        }
+        let tone_mapped = self.hdr.is_some() && tone_map != "none";
+        if let Some(info) = self.hdr.filter(|_| tone_mapped) {
+            img = hdr::tone_map(&img, info, tone_map)?;
+        }
+
+        // A physical print size stands in for max_width / max_height, and
+        // may enlarge.
+        let sized;
+        let options = match options.print_size()? {
+            Some((max_width, max_height)) => {
+                sized = ExportOptions {
+                    max_width,
+                    max_height,
+                    ..options.clone()
+                };
+                &sized
+            }
+            None => options,
+        };
+
+        let orientation = options.orientation_override.or_else(|| {
+            options
+                .apply_exif_orientation
+                .unwrap_or(false)
+                .then_some(self.exif_orientation)
+                .flatten()
+        });
+        if let Some(value) = orientation {
+            let orientation = Orientation::from_exif(value)
+                .ok_or_else(|| format!("Invalid orientation: {} (expected 1-8)", value))?;
+            img.apply_orientation(orientation);
+        }
+        let source_dimensions = (img.width(), img.height());
+
         // Resize if needed
-        if let (Some(max_width), Some(max_height)) = (options.max_width, options.max_height) {
-            img = self.resize_image(img, max_width, max_height);
-        } else if let Some(max_width) = options.max_width {
-            let aspect_ratio = img.height() as f32 / img.width() as f32;
-            let new_height = (max_width as f32 * aspect_ratio) as u32;
-            img = img.resize(max_width, new_height, FilterType::Lanczos3);
-        } else if let Some(max_height) = options.max_height {
-            let aspect_ratio = img.width() as f32 / img.height() as f32;
-            let new_width = (max_height as f32 * aspect_ratio) as u32;
-            img = img.resize(new_width, max_height, FilterType::Lanczos3);
+        let mut resize_to = self.target_dimensions(img.width(), img.height(), options);
+        let mut downscaled_for_memory = None;
+        if let Some(budget) = options.memory_budget {
+            // The padded canvas has fixed dimensions, so shrinking cannot fit it.
+            if let (true, Some(width), Some(height)) =
+                (options.pad_to_exact.unwrap_or(false), options.max_width, options.max_height)
+            {
+                let canvas = width as u64 * height as u64 * 4;
+                if canvas > budget as u64 {
+                    return Err(format!(
+                        "pad_to_exact canvas of {}x{} needs {} bytes, more than the {} byte memory budget",
+                        width, height, canvas, budget
+                    ));
+                }
+            }
+            let (width, height) = resize_to.unwrap_or((img.width(), img.height()));
+            let needed = width as u64 * height as u64 * 4;
+            if needed > budget as u64 {
+                let scale = (budget as f64 / needed as f64).sqrt();
+                let shrink = |side: u32| ((side as f64 * scale) as u32).max(1);
+                resize_to = Some((shrink(width), shrink(height)));
+            }
+            downscaled_for_memory = Some(needed > budget as u64);
+        }
+        if let Some((new_width, new_height)) = resize_to {
+            let upscaling = new_width > img.width() || new_height > img.height();
+            let filter = match options.upscale_filter.as_deref() {
+                Some(name) if upscaling => resize::parse_filter(name)
+                    .ok_or_else(|| format!("Unsupported upscale filter: {}", name))?,
+                _ => FilterType::Lanczos3,
+            };
+            let resized = resize::resize(&img, new_width, new_height, filter);
+            img = if options.clamp_overshoot.unwrap_or(false) {
+                resize::clamp_overshoot(&img, &resized)
+            } else {
+                resized
+            };
+        }
+
+        // Raise the JPEG quality in proportion to the downscale. Smart quality
+        // and `max_bytes` still pick their own.
+        let adjusted;
+        let options = if options.adaptive_quality.unwrap_or(false) && img.width() < source_dimensions.0 {
+            let scale = img.width() as f32 / source_dimensions.0 as f32;
+            adjusted = ExportOptions {
+                quality_jpeg: Some(quality::adaptive_jpeg_quality(options.jpeg_quality(), scale)),
+                ..options.clone()
+            };
+            &adjusted
+        } else {
+            options
+        };
+
+        if let Some(ref kernel) = options.apply_kernel {
+            img = filters::convolve(&img, kernel, options.kernel_size.unwrap_or(3))?;
+        }
+
+        if options.auto_white_balance.unwrap_or(false) {
+            img = filters::auto_white_balance(&img);
+        }
+
+        if let Some(ref curve) = options.curve {
+            img = filters::apply_curve(&img, curve)?;
+        }
+
+        // Pad after the pixel edits so the fill keeps its exact color.
+        if options.pad_to_exact.unwrap_or(false) {
+            let (Some(width), Some(height)) = (options.max_width, options.max_height) else {
+                return Err("pad_to_exact needs both max_width and max_height".to_string());
+            };
+            let background = options.background_color.unwrap_or(DEFAULT_PAD_COLOR);
+            img = resize::pad(&img, width, height, background);
+        }
+
+        // Sign the final pixels; LSB marking has to be the last pixel edit.
+        let mut signature = None;
+        if let Some(ref prov) = options.embed_provenance {
+            let value = prov
+                .signature
+                .clone()
+                .unwrap_or_else(|| provenance::default_signature(&img));
+            if prov.lsb.unwrap_or(false) {
+                let format = normalize_format(&options.format.to_lowercase()).to_string();
+                if !["png", "webp"].contains(&format.as_str()) || !options.exact_pixels() {
+                    return Err(
+                        "LSB provenance needs exact PNG or WebP output (no dither or near-lossless)"
+                            .to_string(),
+                    );
+                }
+                img = provenance::embed_lsb(&img, &value)?;
+            }
+            if prov.metadata.unwrap_or(true) {
+                signature = Some(value);
+            }
         }
 
         let (width, height) = (img.width(), img.height());
+        let mut report = EncodeReport::default();
 
         // Convert to bytes based on format
-        let data = match options.format.to_lowercase().as_str() {
-            "jpeg" | "jpg" => self.encode_jpeg(&img, options.quality)?,
-            "png" => self.encode_png(&img)?,
-            "webp" => self.encode_webp(&img, options.quality)?,
-            "original" => {
-                return Err("Format 'original' must be handled as passthrough".to_string())
-            }
-            _ => return Err(format!("Unsupported format: {}", options.format)),
+        let (format, mut data) = match options.format.to_lowercase().as_str() {
+            "auto" => self.encode_auto(&img, options, deadline, &mut report)?,
+            format => {
+                let data = self.encode_format(&img, format, options, deadline, &mut report)?;
+                (normalize_format(format).to_string(), data)
+            }
         };
 
+        // JPEG output got its EXIF from `encode_format`, with the thumbnail.
+        if !exif_fields.is_empty() && format != "jpeg" {
+            let result = match format.as_str() {
+                "png" if options.png_minimal.unwrap_or(false) => {
+                    Err("png_minimal allows no eXIf chunk".to_string())
+                }
+                "png" => metadata::insert_png_chunk(&data, b"eXIf", &metadata::build_exif(&exif_fields, None)),
+                other => Err(format!("No EXIF carrier for {}", other)),
+            };
+            match result {
+                Ok(with_exif) => data = with_exif,
+                Err(e) => report.warning = Some(format!("Injected metadata not written: {}", e)),
+            }
+        }
+
+        if let Some(dpi) = options.dpi {
+            let result = if format == "png" && options.png_minimal.unwrap_or(false) {
+                Err("png_minimal allows no pHYs chunk".to_string())
+            } else {
+                metadata::set_dpi(&format, &data, dpi)
+            };
+            match result {
+                Ok(tagged) => data = tagged,
+                Err(e) => report.warning = Some(format!("DPI not written: {}", e)),
+            }
+        }
+
+        if let Some(ref profile) = options.icc_profile {
+            let result = if format == "png" && options.png_minimal.unwrap_or(false) {
+                Err("png_minimal allows no iCCP chunk".to_string())
+            } else {
+                metadata::embed_icc(&format, &data, profile)
+            };
+            match result {
+                Ok(tagged) => data = tagged,
+                Err(e) => report.warning = Some(format!("ICC profile not written: {}", e)),
+            }
+        }
+
+        if let Some(ref signature) = signature {
+            let result = if format == "png" && options.png_minimal.unwrap_or(false) {
+                Err("png_minimal allows no text chunks".to_string())
+            } else {
+                provenance::embed_metadata(&format, &data, signature)
+            };
+            match result {
+                Ok(signed) => data = signed,
+                Err(e) => report.warning = Some(format!("Provenance metadata not written: {}", e)),
+            }
+        }
+
+        let mut kept_original = None;
+        if options.keep_smaller.unwrap_or(false) {
+            if self.source.is_none() {
+                report.warning = Some(
+                    "keep_smaller needs the encoded input; load it with keep_source".to_string(),
+                );
+            }
+            let original = self
+                .source
+                .as_deref()
+                .filter(|_| self.full_size.is_none() && (width, height) == source_dimensions)
+                .filter(|_| !options.edits_pixels() && exif_fields.is_empty())
+                .filter(|_| options.dpi.is_none() && options.icc_profile.is_none() && !tone_mapped)
+                .filter(|original| {
+                    image::guess_format(original).ok() == image::ImageFormat::from_extension(&format)
+                })
+                .filter(|original| original.len() <= data.len());
+            if let Some(original) = original {
+                data = original.to_vec();
+            }
+            kept_original = Some(original.is_some());
+        }
+
         let filename = options.filename.clone().unwrap_or_else(|| {
-            let extension = match options.format.to_lowercase().as_str() {
-                "jpeg" | "jpg" => "jpg",
+            let extension = match format.as_str() {
+                "jpeg" => "jpg",
                 "png" => "png",
                 "webp" => "webp",
+                "qoi" => "qoi",
                 _ => "jpg",
             };
             format!("lumilio-export.{}", extension)
         });
 
+        // The processor keeps its decoded copy while a working clone is
+        // resized, converted to the encoder's pixel layout and encoded.
+        let output_bytes = data.len() as u64;
+        let resized_bytes = if (width, height) != source_dimensions {
+            img.as_bytes().len() as u64
+        } else {
+            0
+        };
+        let encode_bytes = width as u64 * height as u64 * img.color().channel_count().max(3) as u64;
+        let memory = MemoryStats {
+            input_bytes: self.source_bytes as u64,
+            decoded_bytes,
+            output_bytes,
+            peak_estimate: self.source.as_ref().map_or(0, |s| s.len() as u64)
+                + decoded_bytes * 2
+                + resized_bytes
+                + encode_bytes
+                + output_bytes,
+        };
+
         Ok(ExportResult {
             success: true,
             data: Some(data),
@@ -208,23 +1006,255 @@ impl ImageProcessor {
             error: None,
             width,
             height,
+            warning: report.warning,
+            memory: Some(memory),
+            format: Some(format),
+            budget_met: deadline.is_set().then(|| !deadline.expired()),
+            requested_width: options.max_width,
+            requested_height: options.max_height,
+            was_resized: resize_to.is_some_and(|size| size != source_dimensions),
+            was_cropped: false,
+            quality: report.quality,
+            max_bytes_met: report.max_bytes_met,
+            kept_original,
+            downscaled_for_memory,
         })
     }
 
-    fn resize_image(&self, img: DynamicImage, max_width: u32, max_height: u32) -> DynamicImage {
-        let (width, height) = (img.width(), img.height());
+    fn encode_format(
+        &self,
+        img: &DynamicImage,
+        format: &str,
+        options: &ExportOptions,
+        deadline: Deadline,
+        report: &mut EncodeReport,
+    ) -> Result<Vec<u8>, String> {
+        if let Some(ref method) = options.dither {
+            if format != "png" {
+                return Err(format!("Dithered output is 1-bit PNG, not {}", format));
+            }
+            let bilevel = filters::dither(img, method)?;
+            return self.encode_png_bilevel(&bilevel, options.png_compression());
+        }
+
+        match format {
+            "jpeg" | "jpg" => {
+                let data = if let Some(max_bytes) = options.max_bytes {
+                    let (data, quality, met) = self.encode_jpeg_max_bytes(
+                        img,
+                        max_bytes as usize,
+                        options.quality_min.unwrap_or(0.1),
+                        options.quality_max.unwrap_or_else(|| options.jpeg_quality()),
+                        deadline,
+                    )?;
+                    report.quality = Some(quality);
+                    report.max_bytes_met = Some(met);
+                    data
+                } else {
+                    let quality = if options.smart_quality.unwrap_or(false) {
+                        quality::smart_jpeg_quality(img, deadline)?
+                    } else {
+                        options.jpeg_quality()
+                    };
+                    report.quality = Some(quality);
+                    self.encode_jpeg(img, quality)?
+                };
+                let fields = options.exif_fields()?;
+                let preview = match options.embed_thumbnail {
+                    Some(max_size) => {
+                        let preview = img.thumbnail(max_size, max_size);
+                        Some(self.encode_jpeg(&preview, EMBEDDED_THUMBNAIL_QUALITY)?)
+                    }
+                    None => None,
+                };
+                if fields.is_empty() && preview.is_none() {
+                    return Ok(data);
+                }
+                match metadata::embed_exif(&data, &fields, preview.as_deref()) {
+                    Ok(with_exif) => Ok(with_exif),
+                    Err(e) if preview.is_some() => {
+                        report.warning = Some(format!("EXIF thumbnail not embedded: {}", e));
+                        if fields.is_empty() {
+                            Ok(data)
+                        } else {
+                            metadata::embed_exif(&data, &fields, None)
+                        }
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+            "png" if options.png_palette.unwrap_or(false) => {
+                let quantize = options.allows_quantization();
+                match self.encode_png_palette(img, options.png_compression(), quantize)? {
+                    Some((data, quantized)) => {
+                        if quantized {
+                            report.warning = Some(
+                                "Image has more than 256 colors; quantized to a 256-color palette"
+                                    .to_string(),
+                            );
+                        }
+                        Ok(data)
+                    }
+                    None => {
+                        report.warning = Some(
+                            "Image has more than 256 colors and LSB provenance needs exact pixels; \
+                             exported as truecolor PNG"
+                                .to_string(),
+                        );
+                        self.encode_png(img, options.png_compression())
+                    }
+                }
+            }
+            "png" => self.encode_png(img, options.png_compression()),
+            "webp" => match options.webp_near_lossless {
+                Some(level) => {
+                    self.encode_webp(&filters::near_lossless(img, level), options.webp_quality())
+                }
+                None => self.encode_webp(img, options.webp_quality()),
+            },
+            "qoi" => self.encode_qoi(img),
+            "original" => Err("Format 'original' must be handled as passthrough".to_string()),
+            _ => Err(format!("Unsupported format: {}", format)),
+        }
+    }
+
+    /// Encode in every candidate format and keep the smallest output.
+    ///
+    /// WebP is tried lossless since that is the only WebP mode available, and
+    /// JPEG is skipped for images with alpha so transparency is never dropped.
+    /// A candidate that fails to encode (WebP past its 16383 pixel limit, for
+    /// one) is skipped; only when every candidate fails is its error returned.
+    /// Once `deadline` expires the smallest output so far is kept.
+    fn encode_auto(
+        &self,
+        img: &DynamicImage,
+        options: &ExportOptions,
+        deadline: Deadline,
+        report: &mut EncodeReport,
+    ) -> Result<(String, Vec<u8>), String> {
+        let candidates = options
+            .auto_formats
+            .clone()
+            .unwrap_or_else(|| DEFAULT_AUTO_FORMATS.iter().map(|f| f.to_string()).collect());
+        let webp_options = ExportOptions {
+            quality_webp: Some(1.0),
+            ..options.clone()
+        };
+
+        let mut best: Option<(String, Vec<u8>, EncodeReport)> = None;
+        let mut last_error = None;
+        for candidate in &candidates {
+            if best.is_some() && deadline.expired() {
+                break;
+            }
+            let format = normalize_format(&candidate.to_lowercase()).to_string();
+            if format == "jpeg" && img.color().has_alpha() {
+                continue;
+            }
+            let candidate_options = if format == "webp" { &webp_options } else { options };
+            let mut candidate_report = EncodeReport::default();
+            let data = match self.encode_format(
+                img,
+                &format,
+                candidate_options,
+                deadline,
+                &mut candidate_report,
+            ) {
+                Ok(data) => data,
+                Err(e) => {
+                    last_error = Some(format!("{}: {}", format, e));
+                    continue;
+                }
+            };
+            if best.as_ref().map_or(true, |(_, smallest, _)| data.len() < smallest.len()) {
+                best = Some((format, data, candidate_report));
+            }
+        }
+
+        let (format, data, best_report) = best.ok_or_else(|| match last_error {
+            Some(e) => format!("No candidate format could encode the image, last error: {}", e),
+            None => "No usable candidate format for auto export".to_string(),
+        })?;
+        *report = best_report;
+        Ok((format, data))
+    }
+
+    /// Highest JPEG quality in `min..=max` whose output fits in `max_bytes`,
+    /// found by binary search over whole-percent qualities. When even `min`
+    /// is too large it is used anyway and the budget is reported as missed.
+    /// An embedded EXIF thumbnail is added afterwards and not counted.
+    fn encode_jpeg_max_bytes(
+        &self,
+        img: &DynamicImage,
+        max_bytes: usize,
+        min: f32,
+        max: f32,
+        deadline: Deadline,
+    ) -> Result<(Vec<u8>, f32, bool), String> {
+        let percent = |q: f32| (q * 100.0).round().clamp(1.0, 100.0) as i32;
+        let (mut lo, mut hi) = (percent(min), percent(max));
+
+        let mut best: Option<(Vec<u8>, i32)> = None;
+        while lo <= hi && !deadline.expired() {
+            let mid = lo + (hi - lo) / 2;
+            let data = self.encode_jpeg(img, mid as f32 / 100.0)?;
+            if data.len() <= max_bytes {
+                best = Some((data, mid));
+                lo = mid + 1;
+            } else {
+                hi = mid - 1;
+            }
+        }
+
+        match best {
+            Some((data, quality)) => Ok((data, quality as f32 / 100.0, true)),
+            None => {
+                let quality = percent(min) as f32 / 100.0;
+                let data = self.encode_jpeg(img, quality)?;
+                let met = data.len() <= max_bytes;
+                Ok((data, quality, met))
+            }
+        }
+    }
 
-        let width_ratio = max_width as f32 / width as f32;
-        let height_ratio = max_height as f32 / height as f32;
+    /// Output size requested by `max_width`/`max_height`, or `None` when the
+    /// image keeps its size. Both bounds together only ever shrink; a single
+    /// bound scales to it exactly, which may enlarge.
+    fn target_dimensions(
+        &self,
+        width: u32,
+        height: u32,
+        options: &ExportOptions,
+    ) -> Option<(u32, u32)> {
+        let target = match (options.max_width, options.max_height) {
+            (Some(max_width), Some(max_height)) => {
+                let width_ratio = max_width as f32 / width as f32;
+                let height_ratio = max_height as f32 / height as f32;
+                let ratio = width_ratio.min(height_ratio);
 
-        let ratio = width_ratio.min(height_ratio);
+                if ratio < 1.0 || (ratio > 1.0 && options.has_print_size()) {
+                    Some(((width as f32 * ratio) as u32, (height as f32 * ratio) as u32))
+                } else {
+                    None
+                }
+            }
+            (Some(max_width), None) => {
+                let aspect_ratio = height as f32 / width as f32;
+                Some((max_width, (max_width as f32 * aspect_ratio) as u32))
+            }
+            (None, Some(max_height)) => {
+                let aspect_ratio = width as f32 / height as f32;
+                Some(((max_height as f32 * aspect_ratio) as u32, max_height))
+            }
+            (None, None) => None,
+        };
 
-        if ratio < 1.0 {
-            let new_width = (width as f32 * ratio) as u32;
-            let new_height = (height as f32 * ratio) as u32;
-            img.resize(new_width, new_height, FilterType::Lanczos3)
+        // A width-only or height-only bound can enlarge; never_upscale keeps
+        // the source size instead.
+        if options.never_upscale.unwrap_or(false) {
+            target.filter(|&(w, h)| w <= width && h <= height)
         } else {
-            img
+            target
         }
     }
 
@@ -245,6 +1275,16 @@ impl ImageProcessor {
                     )
                     .map_err(|e| format!("JPEG encoding error: {}", e))?;
             }
+            image::ColorType::L8 => {
+                encoder
+                    .encode(
+                        img.as_luma8().unwrap().as_raw(),
+                        img.width(),
+                        img.height(),
+                        ExtendedColorType::L8,
+                    )
+                    .map_err(|e| format!("JPEG encoding error: {}", e))?;
+            }
             _ => {
                 let rgb_img = img.to_rgb8();
                 encoder
@@ -261,9 +1301,14 @@ impl ImageProcessor {
         Ok(buffer)
     }
 
-    fn encode_png(&self, img: &DynamicImage) -> Result<Vec<u8>, String> {
+    fn encode_png(
+        &self,
+        img: &DynamicImage,
+        compression: CompressionType,
+    ) -> Result<Vec<u8>, String> {
         let mut buffer = Vec::new();
-        let encoder = PngEncoder::new(&mut buffer);
+        let encoder =
+            PngEncoder::new_with_quality(&mut buffer, compression, PngFilterType::Adaptive);
 
         match img.color() {
             image::ColorType::Rgba8 => {
@@ -286,6 +1331,16 @@ impl ImageProcessor {
                     )
                     .map_err(|e| format!("PNG encoding error: {}", e))?;
             }
+            image::ColorType::L8 => {
+                encoder
+                    .write_image(
+                        img.as_luma8().unwrap().as_raw(),
+                        img.width(),
+                        img.height(),
+                        ExtendedColorType::L8,
+                    )
+                    .map_err(|e| format!("PNG encoding error: {}", e))?;
+            }
             _ => {
                 let rgba_img = img.to_rgba8();
                 encoder
@@ -302,8 +1357,134 @@ impl ImageProcessor {
         Ok(buffer)
     }
 
-    fn encode_webp(&self, img: &DynamicImage, quality: f32) -> Result<Vec<u8>, String> {
-        if quality < 1.0 {
+    /// Encode as an indexed PNG, packing indices into 1/2/4 bits for tiny
+    /// palettes. Images with at most 256 distinct RGBA colors keep them
+    /// exactly. Others are reduced with `filters::median_cut` when `quantize`
+    /// is set, which the second value of the result reports, and give `None`
+    /// otherwise so the caller can fall back to truecolor.
+    fn encode_png_palette(
+        &self,
+        img: &DynamicImage,
+        compression: CompressionType,
+        quantize: bool,
+    ) -> Result<Option<(Vec<u8>, bool)>, String> {
+        let rgba_img = img.to_rgba8();
+        let mut palette: Vec<[u8; 4]> = Vec::new();
+        let mut lookup: HashMap<[u8; 4], u8> = HashMap::new();
+        let mut indices = Vec::with_capacity((img.width() * img.height()) as usize);
+        let mut quantized = false;
+
+        for pixel in rgba_img.pixels() {
+            let index = match lookup.get(&pixel.0) {
+                Some(&index) => index,
+                None => {
+                    if palette.len() == 256 {
+                        quantized = true;
+                        break;
+                    }
+                    let index = palette.len() as u8;
+                    palette.push(pixel.0);
+                    lookup.insert(pixel.0, index);
+                    index
+                }
+            };
+            indices.push(index);
+        }
+        if quantized {
+            if !quantize {
+                return Ok(None);
+            }
+            (palette, indices) = filters::median_cut(&rgba_img, 256);
+        }
+
+        let (bit_depth, bits) = match palette.len() {
+            0..=2 => (png::BitDepth::One, 1),
+            3..=4 => (png::BitDepth::Two, 2),
+            5..=16 => (png::BitDepth::Four, 4),
+            _ => (png::BitDepth::Eight, 8),
+        };
+
+        let width = img.width() as usize;
+        let row_bytes = (width * bits + 7) / 8;
+        let mut packed = vec![0u8; row_bytes * img.height() as usize];
+        for (y, row) in indices.chunks(width).enumerate() {
+            for (x, &index) in row.iter().enumerate() {
+                let bit = x * bits;
+                packed[y * row_bytes + bit / 8] |= index << (8 - bits - bit % 8);
+            }
+        }
+
+        let rgb_palette: Vec<u8> = palette.iter().flat_map(|c| [c[0], c[1], c[2]]).collect();
+        let mut alpha: Vec<u8> = palette.iter().map(|c| c[3]).collect();
+        while alpha.last() == Some(&255) {
+            alpha.pop();
+        }
+
+        let mut buffer = Vec::new();
+        let mut encoder = png::Encoder::new(&mut buffer, img.width(), img.height());
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(bit_depth);
+        encoder.set_compression(png_crate_compression(compression));
+        encoder.set_palette(rgb_palette);
+        if !alpha.is_empty() {
+            encoder.set_trns(alpha);
+        }
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&packed))
+            .map_err(|e| format!("PNG encoding error: {}", e))?;
+
+        Ok(Some((buffer, quantized)))
+    }
+
+    /// Encode a black-and-white image as a 1-bit grayscale PNG.
+    fn encode_png_bilevel(
+        &self,
+        img: &GrayImage,
+        compression: CompressionType,
+    ) -> Result<Vec<u8>, String> {
+        let (width, height) = img.dimensions();
+        let row_bytes = (width as usize + 7) / 8;
+        let mut packed = vec![0u8; row_bytes * height as usize];
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if pixel[0] >= 128 {
+                packed[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+
+        let mut buffer = Vec::new();
+        let mut encoder = png::Encoder::new(&mut buffer, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        encoder.set_compression(png_crate_compression(compression));
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&packed))
+            .map_err(|e| format!("PNG encoding error: {}", e))?;
+
+        Ok(buffer)
+    }
+
+    /// QOI: lossless like PNG at similar sizes, but far faster to encode
+    /// and decode, which suits intermediates between editing steps. Only
+    /// 8-bit RGB and RGBA exist in QOI, so other layouts are converted.
+    fn encode_qoi(&self, img: &DynamicImage) -> Result<Vec<u8>, String> {
+        let mut buffer = Vec::new();
+        let encoder = QoiEncoder::new(&mut buffer);
+        let encoded = if img.color().has_alpha() {
+            let rgba = img.to_rgba8();
+            encoder.write_image(rgba.as_raw(), img.width(), img.height(), ExtendedColorType::Rgba8)
+        } else {
+            let rgb = img.to_rgb8();
+            encoder.write_image(rgb.as_raw(), img.width(), img.height(), ExtendedColorType::Rgb8)
+        };
+        encoded.map_err(|e| format!("QOI encoding error: {}", e))?;
+
+        Ok(buffer)
+    }
+
+    fn encode_webp(&self, img: &DynamicImage, quality: f32) -> Result<Vec<u8>, String> {
+        if quality < 1.0 {
             return Err(
                 "Current WebP encoder supports lossless output only (quality must be 1.0)"
                     .to_string(),
@@ -358,7 +1539,9 @@ pub fn get_supported_formats() -> Array {
     formats.set(0, JsValue::from_str("jpeg"));
     formats.set(1, JsValue::from_str("png"));
     formats.set(2, JsValue::from_str("webp"));
-    formats.set(3, JsValue::from_str("original"));
+    formats.set(3, JsValue::from_str("qoi"));
+    formats.set(4, JsValue::from_str("original"));
+    formats.set(5, JsValue::from_str("auto"));
     formats
 }
 
@@ -369,36 +1552,167 @@ pub fn validate_export_options(options_js: &JsValue) -> bool {
             let format = options.format.to_lowercase();
 
             // Validate format
-            let valid_formats = ["jpeg", "jpg", "png", "webp", "original"];
+            let valid_formats = ["jpeg", "jpg", "png", "webp", "qoi", "auto", "original"];
             if !valid_formats.contains(&format.as_str()) {
                 return false;
             }
 
+            if let Some(ref candidates) = options.auto_formats {
+                let encodable = ["jpeg", "jpg", "png", "webp"];
+                if candidates.is_empty()
+                    || !candidates
+                        .iter()
+                        .all(|c| encodable.contains(&c.to_lowercase().as_str()))
+                {
+                    return false;
+                }
+            }
+
             // Validate quality
-            if options.quality < 0.1 || options.quality > 1.0 {
+            if options.quality_preset.is_some() && options.preset().is_none() {
                 return false;
             }
 
+            for quality in [options.quality, options.quality_jpeg, options.quality_webp]
+                .into_iter()
+                .flatten()
+            {
+                if !(0.1..=1.0).contains(&quality) {
+                    return false;
+                }
+            }
+
             // Current runtime only supports lossless WebP encoding.
-            if format == "webp" && options.quality < 1.0 {
+            if options.check_webp_quality().is_err()
+                || (format == "webp" && options.webp_quality() < 1.0)
+            {
+                return false;
+            }
+
+            if options.max_bytes.is_some() {
+                if options.max_bytes == Some(0) || options.smart_quality.unwrap_or(false) {
+                    return false;
+                }
+                let min = options.quality_min.unwrap_or(0.1);
+                let max = options.quality_max.unwrap_or_else(|| options.jpeg_quality());
+                if !(0.1..=1.0).contains(&min) || !(0.1..=1.0).contains(&max) || min > max {
+                    return false;
+                }
+            }
+
+            if let Some(ref prov) = options.embed_provenance {
+                if prov.lsb.unwrap_or(false)
+                    && (!["png", "webp"].contains(&format.as_str()) || !options.exact_pixels())
+                {
+                    return false;
+                }
+            }
+
+            if let Some(size) = options.embed_thumbnail {
+                if size == 0 || size > MAX_EMBEDDED_THUMBNAIL {
+                    return false;
+                }
+            }
+
+            if let Some(ref curve) = options.curve {
+                if curve.validate().is_err() {
+                    return false;
+                }
+            }
+
+            if options.exif_fields().is_err() {
+                return false;
+            }
+
+            if options.webp_near_lossless.is_some_and(|level| level > 100) {
+                return false;
+            }
+
+            if options.memory_budget == Some(0) {
+                return false;
+            }
+
+            let print_size = match options.print_size() {
+                Ok(size) => size,
+                Err(_) => return false,
+            };
+            if options.dpi.is_some_and(|dpi| !dpi.is_finite() || dpi <= 0.0) {
+                return false;
+            }
+
+            if options
+                .tone_map
+                .as_deref()
+                .is_some_and(|name| !hdr::TONE_MAPS.contains(&name))
+            {
                 return false;
             }
 
+            if options
+                .icc_profile
+                .as_deref()
+                .is_some_and(|profile| !metadata::is_icc_profile(profile))
+            {
+                return false;
+            }
+
+            let bounds = print_size.unwrap_or((options.max_width, options.max_height));
+            if options.pad_to_exact.unwrap_or(false) {
+                let (Some(width), Some(height)) = bounds else {
+                    return false;
+                };
+                if options
+                    .memory_budget
+                    .is_some_and(|budget| width as u64 * height as u64 * 4 > budget as u64)
+                {
+                    return false;
+                }
+            }
+
+            if let Some(ref method) = options.dither {
+                if format != "png" || !filters::DITHER_METHODS.contains(&method.as_str()) {
+                    return false;
+                }
+            }
+
+            if let Some(value) = options.orientation_override {
+                if !(1..=8).contains(&value) {
+                    return false;
+                }
+            }
+
             // "original" must stay passthrough.
-            if format == "original" && (options.max_width.is_some() || options.max_height.is_some())
+            if format == "original"
+                && (options.max_width.is_some()
+                    || options.max_height.is_some()
+                    || options.orientation_override.is_some()
+                    || options.apply_exif_orientation.unwrap_or(false))
             {
                 return false;
             }
 
+            if let Some(ref filter) = options.upscale_filter {
+                if resize::parse_filter(filter).is_none() {
+                    return false;
+                }
+            }
+
+            if let Some(ref kernel) = options.apply_kernel {
+                let size = options.kernel_size.unwrap_or(3);
+                if !filters::KERNEL_SIZES.contains(&size) || kernel.len() != (size * size) as usize {
+                    return false;
+                }
+            }
+
             // Validate dimensions
             if let Some(width) = options.max_width {
-                if width == 0 || width > 16384 {
+                if width == 0 || width > MAX_DIMENSION {
                     return false;
                 }
             }
 
             if let Some(height) = options.max_height {
-                if height == 0 || height > 16384 {
+                if height == 0 || height > MAX_DIMENSION {
                     return false;
                 }
             }
@@ -409,6 +1723,862 @@ pub fn validate_export_options(options_js: &JsValue) -> bool {
     }
 }
 
+/// Read the EXIF block of an encoded image as a plain `{ tag: value }` object.
+///
+/// Images without EXIF yield an empty object rather than an error.
+#[wasm_bindgen]
+pub fn read_exif(buffer: &[u8]) -> Result<JsValue, JsError> {
+    let fields = metadata::read_exif_fields(buffer).map_err(|e| JsError::new(&e))?;
+    let serializer = serde_wasm_bindgen::Serializer::new().serialize_maps_as_objects(true);
+    fields
+        .serialize(&serializer)
+        .map_err(|e| JsError::new(&format!("EXIF serialization error: {}", e)))
+}
+
+/// Decode and export every buffer in `buffers` with the same options.
+///
+/// A bad input yields a per-item error instead of failing the whole batch,
+/// unless `fail_fast` is set, in which case the first failure is thrown.
+///
+/// Items are processed strictly one at a time: each result is handed to JS
+/// and its Rust buffers are freed before the next input is copied in. With
+/// `max_concurrent_bytes`, an item whose estimated working set (encoded
+/// input plus decoded pixels, read from its header) exceeds the budget is
+/// rejected before decoding instead of risking an out-of-memory abort.
+///
+/// Every item is screened with `decode::check_input` before it is decoded,
+/// so truncated, unrecognised and oversized inputs fail on their own.
+/// Beyond that, a decoder panic is reported as that item's error only where
+/// the build unwinds panics; see `utils::catch_panic`.
+#[wasm_bindgen]
+pub fn export_batch(
+    buffers: Array,
+    options_js: &JsValue,
+    fail_fast: Option<bool>,
+    max_concurrent_bytes: Option<u32>,
+) -> Result<JsValue, JsError> {
+    let options: ExportOptions = serde_wasm_bindgen::from_value(options_js.clone())
+        .map_err(|e| JsError::new(&format!("Invalid options: {}", e)))?;
+    let fail_fast = fail_fast.unwrap_or(false);
+    utils::set_panic_hook();
+
+    let items = Array::new();
+    for (index, value) in buffers.iter().enumerate() {
+        let outcome = match value.dyn_into::<Uint8Array>() {
+            Ok(bytes) => export_item(&bytes.to_vec(), &options, max_concurrent_bytes),
+            Err(_) => Err("Batch item is not a Uint8Array".to_string()),
+        };
+
+        if let Err(ref e) = outcome {
+            if fail_fast {
+                return Err(JsError::new(&format!("Batch item {} failed: {}", index, e)));
+            }
+            console_error!("Batch item {} failed: {}", index, e);
+        }
+        let item = BatchItemResult::new(index as u32, outcome);
+        let item = serde_wasm_bindgen::to_value(&item)
+            .map_err(|e| JsError::new(&format!("Batch serialization error: {}", e)))?;
+        items.push(&item);
+    }
+
+    Ok(items.into())
+}
+
+/// Bounded FIFO of pending exports for streaming imports.
+///
+/// JS pushes encoded files as it reads them and polls for results; each
+/// `poll` runs exactly one item through the export pipeline. `push` refuses
+/// new work once `capacity` items are waiting, so the caller must drain
+/// results before reading more files and at most `capacity` encoded inputs
+/// are held in WASM memory at a time.
+#[wasm_bindgen]
+pub struct ProcessingQueue {
+    pending: VecDeque<(u32, Vec<u8>, ExportOptions)>,
+    capacity: usize,
+    next_id: u32,
+}
+
+#[wasm_bindgen]
+impl ProcessingQueue {
+    #[wasm_bindgen(constructor)]
+    pub fn new(capacity: u32) -> Result<ProcessingQueue, JsError> {
+        if capacity == 0 {
+            return Err(JsError::new("Queue capacity must be at least 1"));
+        }
+        utils::set_panic_hook();
+        Ok(ProcessingQueue {
+            pending: VecDeque::with_capacity(capacity as usize),
+            capacity: capacity as usize,
+            next_id: 0,
+        })
+    }
+
+    /// Queue `bytes` for export with `options_js`.
+    ///
+    /// Returns the item's id, which `poll` reports back as `index`, or
+    /// `undefined` when the queue is full. Invalid options throw.
+    pub fn push(&mut self, bytes: &[u8], options_js: &JsValue) -> Result<Option<u32>, JsError> {
+        let options: ExportOptions = serde_wasm_bindgen::from_value(options_js.clone())
+            .map_err(|e| JsError::new(&format!("Invalid options: {}", e)))?;
+        if self.is_full() {
+            return Ok(None);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending.push_back((id, bytes.to_vec(), options));
+        Ok(Some(id))
+    }
+
+    /// Process the oldest pending item and return its `BatchItemResult`, or
+    /// `undefined` when nothing is queued. A failed item is reported in the
+    /// result rather than thrown.
+    pub fn poll(&mut self) -> Result<JsValue, JsError> {
+        let Some((id, bytes, options)) = self.pending.pop_front() else {
+            return Ok(JsValue::UNDEFINED);
+        };
+        let outcome = export_item(&bytes, &options, None);
+        if let Err(ref e) = outcome {
+            console_error!("Queue item {} failed: {}", id, e);
+        }
+        let item = BatchItemResult::new(id, outcome);
+        serde_wasm_bindgen::to_value(&item)
+            .map_err(|e| JsError::new(&format!("Queue serialization error: {}", e)))
+    }
+
+    /// Number of items waiting to be processed.
+    #[wasm_bindgen(getter)]
+    pub fn pending(&self) -> u32 {
+        self.pending.len() as u32
+    }
+
+    #[wasm_bindgen(getter, js_name = isFull)]
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= self.capacity
+    }
+}
+
+/// Payload of a `data:[<media type>];base64,<data>` URL.
+fn decode_data_url(data_url: &str) -> Result<Vec<u8>, String> {
+    use base64::Engine;
+
+    let rest = data_url
+        .strip_prefix("data:")
+        .ok_or("Missing data: prefix")?;
+    let (header, payload) = rest.split_once(',').ok_or("Missing comma after header")?;
+    if !header.to_ascii_lowercase().ends_with(";base64") {
+        return Err("Only base64 data URLs are supported".to_string());
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(payload.trim())
+        .map_err(|e| format!("Invalid base64: {}", e))
+}
+
+/// One `export_batch` or `ProcessingQueue` item, with panics turned into
+/// errors where they unwind. `export_bytes` screens the input before
+/// decoding.
+fn export_item(
+    bytes: &[u8],
+    options: &ExportOptions,
+    max_concurrent_bytes: Option<u32>,
+) -> Result<ExportResult, String> {
+    utils::catch_panic(|| {
+        check_memory_budget(bytes, max_concurrent_bytes)?;
+        export_bytes(bytes, options)
+    })
+}
+
+fn check_memory_budget(bytes: &[u8], budget: Option<u32>) -> Result<(), String> {
+    let Some(budget) = budget else {
+        return Ok(());
+    };
+    match decode::estimate_decoded_bytes(bytes) {
+        Some(decoded) if bytes.len() as u64 + decoded > budget as u64 => Err(format!(
+            "Estimated {} bytes exceeds the {} byte memory budget",
+            bytes.len() as u64 + decoded,
+            budget
+        )),
+        _ => Ok(()),
+    }
+}
+
+fn export_bytes(bytes: &[u8], options: &ExportOptions) -> Result<ExportResult, String> {
+    let img = decode::decode_image(bytes).map_err(|e| format!("Failed to load image: {}", e))?;
+    let processor = ImageProcessor {
+        image: None,
+        source_bytes: bytes.len(),
+        full_size: None,
+        source: options.keep_smaller.unwrap_or(false).then(|| bytes.to_vec()),
+        exif_orientation: options
+            .apply_exif_orientation
+            .unwrap_or(false)
+            .then(|| metadata::exif_orientation(bytes))
+            .flatten(),
+        hdr: hdr::detect(bytes),
+    };
+    processor.process_image(img, options)
+}
+
+/// Run an already decoded image through the regular export pipeline.
+fn encode_image(
+    img: DynamicImage,
+    source_bytes: usize,
+    options: &ExportOptions,
+) -> Result<ExportResult, String> {
+    let processor = ImageProcessor {
+        image: None,
+        source_bytes,
+        full_size: None,
+        source: None,
+        exif_orientation: None,
+        hdr: None,
+    };
+    processor.process_image(img, options)
+}
+
+/// Use the luma of `mask_bytes` as the alpha channel of `image_bytes` and
+/// encode the cutout as PNG or WebP.
+///
+/// A mask of a different size is stretched to the image unless
+/// `resize_mask` is `false`, in which case the mismatch is an error.
+#[wasm_bindgen]
+pub fn apply_mask(
+    image_bytes: &[u8],
+    mask_bytes: &[u8],
+    output_format: &str,
+    quality: f32,
+    resize_mask: Option<bool>,
+) -> Result<Vec<u8>, JsError> {
+    let format = output_format.to_lowercase();
+    if format != "png" && format != "webp" {
+        return Err(JsError::new(&format!(
+            "Masked export needs an alpha-capable format (png, webp), got {}",
+            output_format
+        )));
+    }
+
+    let img = decode::decode_image(image_bytes)
+        .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+    let mut mask = decode::decode_image(mask_bytes)
+        .map_err(|e| JsError::new(&format!("Failed to load mask: {}", e)))?;
+
+    if mask.dimensions() != img.dimensions() {
+        if !resize_mask.unwrap_or(true) {
+            return Err(JsError::new(&format!(
+                "Mask is {}x{} but image is {}x{}",
+                mask.width(),
+                mask.height(),
+                img.width(),
+                img.height()
+            )));
+        }
+        mask = mask.resize_exact(img.width(), img.height(), FilterType::Triangle);
+    }
+
+    let alpha = mask.to_luma8();
+    let mut rgba = img.to_rgba8();
+    for (pixel, coverage) in rgba.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = coverage[0];
+    }
+
+    let options = ExportOptions {
+        format,
+        quality: Some(quality),
+        ..Default::default()
+    };
+    encode_image(DynamicImage::ImageRgba8(rgba), image_bytes.len(), &options)
+        .map(|result| result.data.unwrap_or_default())
+        .map_err(|e| JsError::new(&e))
+}
+
+/// Round the corners of `image_bytes` to transparency and encode as PNG or
+/// WebP, both of which keep the alpha channel. `radius` is clamped to half
+/// the shorter side.
+///
+/// With `antialias` (the default) pixels on the arc get partial alpha from
+/// their distance to it; `false` gives a hard in/out edge.
+#[wasm_bindgen]
+pub fn export_rounded(
+    image_bytes: &[u8],
+    radius: u32,
+    format: &str,
+    quality: f32,
+    antialias: Option<bool>,
+) -> Result<Vec<u8>, JsError> {
+    let format = format.to_lowercase();
+    if format != "png" && format != "webp" {
+        return Err(JsError::new(&format!(
+            "Rounded export needs an alpha-capable format (png, webp), got {}",
+            format
+        )));
+    }
+
+    let img = decode::decode_image(image_bytes)
+        .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+    let rounded = apply_rounded_corners(&img, radius, antialias.unwrap_or(true));
+
+    let options = ExportOptions {
+        format,
+        quality: Some(quality),
+        ..Default::default()
+    };
+    encode_image(DynamicImage::ImageRgba8(rounded), image_bytes.len(), &options)
+        .map(|result| result.data.unwrap_or_default())
+        .map_err(|e| JsError::new(&e))
+}
+
+/// Ancillary PNG chunks `rotate_png_lossless` carries over. They describe
+/// color or text and stay valid whatever color type the output ends up
+/// with. `pHYs` is handled separately since its axes swap, and `eXIf` is
+/// dropped because an Orientation tag in it would rotate the image twice.
+const PORTABLE_PNG_CHUNKS: [&[u8; 4]; 8] = [
+    b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"tEXt", b"zTXt", b"iTXt", b"tIME",
+];
+
+/// Rotate a PNG clockwise by 0, 90, 180 or 270 degrees and re-encode it as
+/// PNG. Pixels move without resampling, 16-bit samples stay 16-bit, indexed
+/// sources are written indexed again when their colors still fit a palette,
+/// and color/text chunks are copied from the source.
+#[wasm_bindgen]
+pub fn rotate_png_lossless(buffer: &[u8], degrees: u32) -> Result<Vec<u8>, JsError> {
+    decode::check_input(buffer).map_err(|e| JsError::new(&e))?;
+    let img = image::load_from_memory_with_format(buffer, image::ImageFormat::Png)
+        .map_err(|e| JsError::new(&format!("Failed to load PNG: {}", e)))?;
+    let rotated = match degrees % 360 {
+        0 => img,
+        90 => img.rotate90(),
+        180 => img.rotate180(),
+        270 => img.rotate270(),
+        _ => {
+            return Err(JsError::new(&format!(
+                "Rotation must be a multiple of 90 degrees, got {}",
+                degrees
+            )))
+        }
+    };
+
+    let chunks = metadata::png_chunk_list(buffer);
+    // IHDR color type 3 is indexed color.
+    let indexed = chunks
+        .first()
+        .is_some_and(|(kind, data)| kind == b"IHDR" && data.get(9) == Some(&3));
+
+    let processor = ImageProcessor {
+        image: None,
+        source_bytes: buffer.len(),
+        full_size: None,
+        source: None,
+        exif_orientation: None,
+        hdr: None,
+    };
+    let palette = if indexed {
+        processor
+            .encode_png_palette(&rotated, CompressionType::Default, false)
+            .map_err(|e| JsError::new(&e))?
+            .map(|(data, _)| data)
+    } else {
+        None
+    };
+    let mut output = match palette {
+        Some(data) => data,
+        None => {
+            // Unlike `encode_png`, keep the decoded layout (L8, LA16, RGB16, ...).
+            let mut data = Vec::new();
+            rotated
+                .write_with_encoder(PngEncoder::new(&mut data))
+                .map_err(|e| JsError::new(&format!("PNG encoding error: {}", e)))?;
+            data
+        }
+    };
+
+    let swap_axes = degrees % 180 == 90;
+    // Each chunk is inserted right after IHDR, so go backwards to keep order.
+    for (kind, data) in chunks.iter().rev() {
+        let copied = if kind == b"pHYs" && swap_axes && data.len() == 9 {
+            let mut swapped = data.to_vec();
+            swapped[..4].copy_from_slice(&data[4..8]);
+            swapped[4..8].copy_from_slice(&data[..4]);
+            swapped
+        } else if kind == b"pHYs" || PORTABLE_PNG_CHUNKS.contains(&kind) {
+            data.to_vec()
+        } else {
+            continue;
+        };
+        output = metadata::insert_png_chunk(&output, kind, &copied).map_err(|e| JsError::new(&e))?;
+    }
+
+    Ok(output)
+}
+
+/// Clear the alpha of every pixel whose center lies outside the rounded
+/// rectangle. With `antialias`, alpha is instead scaled by the pixel's
+/// approximate coverage: 1 a half pixel inside the arc, 0 a half pixel
+/// outside, linear in between.
+fn apply_rounded_corners(img: &DynamicImage, radius: u32, antialias: bool) -> image::RgbaImage {
+    let mut rgba = img.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let radius = radius.min(width / 2).min(height / 2) as f32;
+    if radius == 0.0 {
+        return rgba;
+    }
+
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        let px = x as f32 + 0.5;
+        let py = y as f32 + 0.5;
+        // Distance into the corner square, zero outside the corner regions.
+        let dx = (radius - px).max(px - (width as f32 - radius)).max(0.0);
+        let dy = (radius - py).max(py - (height as f32 - radius)).max(0.0);
+        if dx == 0.0 && dy == 0.0 {
+            continue;
+        }
+        if antialias {
+            let coverage = (radius - (dx * dx + dy * dy).sqrt() + 0.5).clamp(0.0, 1.0);
+            pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+        } else if dx * dx + dy * dy > radius * radius {
+            pixel[3] = 0;
+        }
+    }
+
+    rgba
+}
+
+/// Fade the edges of `image_bytes` to transparency and encode as PNG or
+/// WebP. Only alpha changes; colors are kept, so the result blends into
+/// whatever background it is placed on.
+///
+/// `strength` (0.0–1.0) is how far in from the edge the fade reaches, as a
+/// fraction of half the width/height. `shape` is `"ellipse"` (distance from
+/// the center on a stretched circle) or `"rectangle"` (each edge fades on
+/// its own).
+#[wasm_bindgen]
+pub fn add_alpha_vignette(
+    image_bytes: &[u8],
+    strength: f32,
+    shape: &str,
+    format: &str,
+    quality: f32,
+) -> Result<Vec<u8>, JsError> {
+    let format = format.to_lowercase();
+    if format != "png" && format != "webp" {
+        return Err(JsError::new(&format!(
+            "Alpha vignette needs an alpha-capable format (png, webp), got {}",
+            format
+        )));
+    }
+    let elliptical = match shape.to_lowercase().as_str() {
+        "ellipse" => true,
+        "rectangle" => false,
+        _ => return Err(JsError::new(&format!("Unsupported vignette shape: {}", shape))),
+    };
+
+    let img = decode::decode_image(image_bytes)
+        .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+    let faded = apply_alpha_vignette(&img, strength.clamp(0.0, 1.0), elliptical);
+
+    let options = ExportOptions {
+        format,
+        quality: Some(quality),
+        ..Default::default()
+    };
+    encode_image(DynamicImage::ImageRgba8(faded), image_bytes.len(), &options)
+        .map(|result| result.data.unwrap_or_default())
+        .map_err(|e| JsError::new(&e))
+}
+
+/// Scale alpha by a smoothstep falloff that is 1.0 inside `1 - strength` of
+/// the normalized distance from the center and 0.0 at the edge.
+fn apply_alpha_vignette(img: &DynamicImage, strength: f32, elliptical: bool) -> image::RgbaImage {
+    let mut rgba = img.to_rgba8();
+    if strength == 0.0 {
+        return rgba;
+    }
+
+    let (width, height) = rgba.dimensions();
+    let (half_w, half_h) = (width as f32 / 2.0, height as f32 / 2.0);
+    let inner = 1.0 - strength;
+    for (x, y, pixel) in rgba.enumerate_pixels_mut() {
+        let dx = ((x as f32 + 0.5) - half_w).abs() / half_w;
+        let dy = ((y as f32 + 0.5) - half_h).abs() / half_h;
+        let distance = if elliptical {
+            (dx * dx + dy * dy).sqrt()
+        } else {
+            dx.max(dy)
+        };
+        let t = ((distance - inner) / strength).clamp(0.0, 1.0);
+        let falloff = 1.0 - t * t * (3.0 - 2.0 * t);
+        pixel[3] = (pixel[3] as f32 * falloff).round() as u8;
+    }
+
+    rgba
+}
+
+/// Estimate the 1–100 quality an uploaded JPEG was saved at, so
+/// re-exports can be capped at it instead of spending bytes on detail that
+/// is already gone. See `quality::estimate_jpeg_quality` for accuracy.
+#[wasm_bindgen]
+pub fn estimate_jpeg_quality(buffer: &[u8]) -> Result<u8, JsError> {
+    quality::estimate_jpeg_quality(buffer).map_err(|e| JsError::new(&e))
+}
+
+/// Read a provenance signature written by the `embed_provenance` export
+/// option, from XMP metadata and from the pixel LSBs.
+#[wasm_bindgen]
+pub fn read_provenance(buffer: &[u8]) -> Result<JsValue, JsError> {
+    let img = decode::decode_image(buffer)
+        .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+    let found = provenance::Provenance {
+        metadata: provenance::read_metadata(buffer),
+        lsb: provenance::read_lsb(&img),
+    };
+    serde_wasm_bindgen::to_value(&found).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Copy the EXIF and XMP of `source_bytes` into `processed_bytes`, e.g. to
+/// restore capture metadata after a tool that strips it. Pixels are not
+/// touched, and EXIF values such as Orientation are copied as they are, so
+/// they should still describe the processed image.
+///
+/// Only JPEG into JPEG and PNG into PNG are supported; `output_format` must
+/// match both inputs.
+#[wasm_bindgen]
+pub fn transplant_metadata(
+    processed_bytes: &[u8],
+    source_bytes: &[u8],
+    output_format: &str,
+) -> Result<Vec<u8>, JsError> {
+    let format = match output_format.to_lowercase().as_str() {
+        "jpeg" | "jpg" => "jpeg",
+        "png" => "png",
+        other => return Err(JsError::new(&format!("Unsupported format: {}", other))),
+    };
+    let expected = image::ImageFormat::from_extension(format);
+    for (name, bytes) in [("Processed", processed_bytes), ("Source", source_bytes)] {
+        if image::guess_format(bytes).ok() != expected {
+            return Err(JsError::new(&format!("{} image is not {}", name, format)));
+        }
+    }
+
+    metadata::transplant(format, processed_bytes, source_bytes).map_err(|e| JsError::new(&e))
+}
+
+/// Which cells of a `grid_w`×`grid_h` grid differ between two images of the
+/// same size, as `grid_h` rows of `grid_w` booleans. A cell is changed when
+/// any of its pixels differs in any RGBA channel. Cell edges fall at
+/// `i * width / grid_w` (and likewise vertically), so cells differ by at
+/// most one pixel in size.
+#[wasm_bindgen]
+pub fn region_diff(
+    a_bytes: &[u8],
+    b_bytes: &[u8],
+    grid_w: u32,
+    grid_h: u32,
+) -> Result<JsValue, JsError> {
+    let a = decode::decode_image(a_bytes)
+        .map_err(|e| JsError::new(&format!("Failed to load first image: {}", e)))?
+        .to_rgba8();
+    let b = decode::decode_image(b_bytes)
+        .map_err(|e| JsError::new(&format!("Failed to load second image: {}", e)))?
+        .to_rgba8();
+    if a.dimensions() != b.dimensions() {
+        return Err(JsError::new(&format!(
+            "Images differ in size: {}x{} vs {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        )));
+    }
+    let (width, height) = a.dimensions();
+    if grid_w == 0 || grid_h == 0 || grid_w > width || grid_h > height {
+        return Err(JsError::new(&format!(
+            "Grid of {}x{} does not fit a {}x{} image",
+            grid_w, grid_h, width, height
+        )));
+    }
+
+    let mut changed = vec![vec![false; grid_w as usize]; grid_h as usize];
+    for (x, y, pixel) in a.enumerate_pixels() {
+        let cell_x = (x as u64 * grid_w as u64 / width as u64) as usize;
+        let cell_y = (y as u64 * grid_h as u64 / height as u64) as usize;
+        if !changed[cell_y][cell_x] && pixel != b.get_pixel(x, y) {
+            changed[cell_y][cell_x] = true;
+        }
+    }
+
+    serde_wasm_bindgen::to_value(&changed).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Edge length of the downscaled copy `is_grayscale` inspects.
+const GRAYSCALE_PROBE_SIZE: u32 = 256;
+
+/// Whether every pixel of `buffer` has R, G and B within `tolerance` of each
+/// other, i.e. grayscale JPEG would lose nothing visible.
+///
+/// Luma-only sources answer `true` without looking at pixels. Otherwise the
+/// check runs on a copy downscaled to fit 256×256; downscaling averages
+/// neighbours, so small specks of color (a red stamp on a scanned page) can
+/// blend away and report `true`.
+#[wasm_bindgen]
+pub fn is_grayscale(buffer: &[u8], tolerance: u8) -> Result<bool, JsError> {
+    let img = decode::decode_image(buffer)
+        .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+    if !img.color().has_color() {
+        return Ok(true);
+    }
+
+    let probe = img
+        .thumbnail(GRAYSCALE_PROBE_SIZE, GRAYSCALE_PROBE_SIZE)
+        .to_rgb8();
+    Ok(probe.pixels().all(|p| {
+        let [r, g, b] = p.0;
+        r.max(g).max(b) - r.min(g).min(b) <= tolerance
+    }))
+}
+
+/// One tile produced by `tile_image`, positioned in source pixels.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// Split `buffer` into `tile_w`×`tile_h` tiles where neighbours share
+/// `overlap` pixels, encoding each one with `output_format`/`quality`.
+///
+/// Tiles run left to right, top to bottom. The last tile in a row or column
+/// is cut short at the image edge rather than padded.
+#[wasm_bindgen]
+pub fn tile_image(
+    buffer: &[u8],
+    tile_w: u32,
+    tile_h: u32,
+    overlap: u32,
+    output_format: &str,
+    quality: f32,
+) -> Result<JsValue, JsError> {
+    if tile_w == 0 || tile_h == 0 {
+        return Err(JsError::new("Tile size must be non-zero"));
+    }
+    if overlap >= tile_w || overlap >= tile_h {
+        return Err(JsError::new("Overlap must be smaller than the tile size"));
+    }
+
+    let img = decode::decode_image(buffer)
+        .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+    let options = ExportOptions {
+        format: output_format.to_lowercase(),
+        quality: Some(quality),
+        ..Default::default()
+    };
+
+    let mut tiles = Vec::new();
+    for y in tile_starts(img.height(), tile_h, overlap) {
+        for x in tile_starts(img.width(), tile_w, overlap) {
+            let w = tile_w.min(img.width() - x);
+            let h = tile_h.min(img.height() - y);
+            let data = encode_image(img.crop_imm(x, y, w, h), 0, &options)
+                .map_err(|e| JsError::new(&e))?
+                .data
+                .unwrap_or_default();
+            tiles.push(Tile { x, y, w, h, data });
+        }
+    }
+
+    serde_wasm_bindgen::to_value(&tiles).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Offsets of the tiles along one axis: steps of `tile - overlap` until a
+/// tile reaches the end.
+fn tile_starts(length: u32, tile: u32, overlap: u32) -> Vec<u32> {
+    let step = tile - overlap;
+    let mut starts = vec![0];
+    let mut start = 0;
+    while start + tile < length {
+        start += step;
+        starts.push(start);
+    }
+    starts
+}
+
+/// Result of `pack_atlas`: the encoded atlas and where each input went.
+#[derive(Serialize, Debug)]
+pub struct Atlas {
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub placements: Vec<atlas::Placement>,
+}
+
+/// Pack the encoded images of `images_js` into one spritesheet at most
+/// `max_width` wide, `padding` pixels apart, encoded with
+/// `output_format`/`quality`.
+///
+/// Packing is a simple shelf algorithm, see `atlas::shelf_pack`.
+/// `placements[i]` is `{ x, y, w, h }` of `images_js[i]`. An image wider
+/// than `max_width` is an error.
+#[wasm_bindgen]
+pub fn pack_atlas(
+    images_js: Array,
+    max_width: u32,
+    padding: u32,
+    output_format: &str,
+    quality: f32,
+) -> Result<JsValue, JsError> {
+    if max_width == 0 || max_width > MAX_DIMENSION {
+        return Err(JsError::new(&format!("max_width must be 1-{}", MAX_DIMENSION)));
+    }
+    let images = images_js
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let bytes = value
+                .dyn_into::<Uint8Array>()
+                .map_err(|_| format!("Image {} is not a Uint8Array", index))?
+                .to_vec();
+            decode::decode_image(&bytes).map_err(|e| format!("Failed to load image {}: {}", index, e))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| JsError::new(&e))?;
+    if images.is_empty() {
+        return Err(JsError::new("At least one image is required"));
+    }
+
+    let sizes: Vec<(u32, u32)> = images.iter().map(|img| img.dimensions()).collect();
+    let (placements, width, height) =
+        atlas::shelf_pack(&sizes, max_width, padding).map_err(|e| JsError::new(&e))?;
+    if height > MAX_DIMENSION {
+        return Err(JsError::new(&format!(
+            "Atlas would be {} px tall, more than {}",
+            height, MAX_DIMENSION
+        )));
+    }
+
+    let sheet = atlas::compose(&images, &placements, width, height);
+    drop(images);
+    let options = ExportOptions {
+        format: output_format.to_lowercase(),
+        quality: Some(quality),
+        ..Default::default()
+    };
+    let data = encode_image(DynamicImage::ImageRgba8(sheet), 0, &options)
+        .map_err(|e| JsError::new(&e))?
+        .data
+        .unwrap_or_default();
+
+    let result = Atlas {
+        data,
+        width,
+        height,
+        placements,
+    };
+    serde_wasm_bindgen::to_value(&result).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Build a looping animated WebP from `frames_js`, an array of encoded
+/// images of equal size, shown for the matching entry of `delays_js` in
+/// milliseconds. `loop_count` 0 repeats forever.
+///
+/// Frames are stored lossless, the only WebP mode available, so `quality`
+/// must be 1.0 when given.
+#[wasm_bindgen]
+pub fn encode_animated_webp(
+    frames_js: Array,
+    delays_js: &JsValue,
+    loop_count: u16,
+    quality: Option<f32>,
+) -> Result<Vec<u8>, JsError> {
+    if quality.is_some_and(|q| q < 1.0) {
+        return Err(JsError::new(
+            "Current WebP encoder supports lossless output only (quality must be 1.0)",
+        ));
+    }
+    let delays: Vec<u32> = serde_wasm_bindgen::from_value(delays_js.clone())
+        .map_err(|e| JsError::new(&format!("Invalid delays: {}", e)))?;
+
+    let frames = frames_js
+        .iter()
+        .enumerate()
+        .map(|(index, value)| {
+            let bytes = value
+                .dyn_into::<Uint8Array>()
+                .map_err(|_| format!("Frame {} is not a Uint8Array", index))?
+                .to_vec();
+            decode::decode_image(&bytes).map_err(|e| format!("Failed to load frame {}: {}", index, e))
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| JsError::new(&e))?;
+
+    animation::encode_animated_webp(&frames, &delays, loop_count).map_err(|e| JsError::new(&e))
+}
+
+/// One frame produced by `split_frames`.
+#[derive(Serialize, Debug)]
+pub struct AnimationFrame {
+    pub index: u32,
+    pub delay_ms: u32,
+    #[serde(with = "serde_bytes")]
+    pub data: Vec<u8>,
+}
+
+/// Split an animated GIF, WebP or PNG into still images encoded with
+/// `output_format`/`quality`, as `[{ index, delay_ms, data }]`.
+///
+/// Every frame is the full canvas as it looks while shown, with earlier
+/// frames composited underneath, so frames can be edited on their own and
+/// reassembled with `encode_animated_webp`. Any other input, including a
+/// still WebP or PNG, comes back as a single frame with `delay_ms` 0. More
+/// than `animation::MAX_FRAMES` frames is an error.
+#[wasm_bindgen]
+pub fn split_frames(buffer: &[u8], output_format: &str, quality: f32) -> Result<JsValue, JsError> {
+    let options = ExportOptions {
+        format: output_format.to_lowercase(),
+        quality: Some(quality),
+        ..Default::default()
+    };
+    let encode = |img: DynamicImage| {
+        encode_image(img, 0, &options)
+            .map(|result| result.data.unwrap_or_default())
+            .map_err(|e| JsError::new(&e))
+    };
+
+    let mut frames = Vec::new();
+    match animation::decode_frames(buffer).map_err(|e| JsError::new(&e))? {
+        Some(decoded) => {
+            for (index, frame) in decoded.enumerate() {
+                if index == animation::MAX_FRAMES {
+                    return Err(JsError::new(&format!(
+                        "Animation has more than {} frames",
+                        animation::MAX_FRAMES
+                    )));
+                }
+                let frame = frame
+                    .map_err(|e| JsError::new(&format!("Failed to decode frame {}: {}", index, e)))?;
+                let (numer, denom) = frame.delay().numer_denom_ms();
+                frames.push(AnimationFrame {
+                    index: index as u32,
+                    delay_ms: (numer as f64 / denom.max(1) as f64).round() as u32,
+                    data: encode(DynamicImage::ImageRgba8(frame.into_buffer()))?,
+                });
+            }
+        }
+        None => {
+            let img = decode::decode_image(buffer)
+                .map_err(|e| JsError::new(&format!("Failed to load image: {}", e)))?;
+            frames.push(AnimationFrame {
+                index: 0,
+                delay_ms: 0,
+                data: encode(img)?,
+            });
+        }
+    }
+
+    serde_wasm_bindgen::to_value(&frames).map_err(|e| JsError::new(&e.to_string()))
+}
+
 // Simple function to test WASM loading
 #[wasm_bindgen]
 pub fn greet(name: &str) -> String {
@@ -430,9 +2600,312 @@ pub fn create_blob(data: &[u8], mime_type: &str) -> Result<Blob, JsValue> {
     Blob::new_with_u8_array_sequence_and_options(&blob_parts, &blob_property_bag)
 }
 
+/// Name of the global allocator compiled into this build, for correlating
+/// out-of-memory reports with the `wee_alloc` feature.
+#[wasm_bindgen]
+pub fn allocator_info() -> String {
+    if cfg!(feature = "wee_alloc") {
+        "wee_alloc".to_string()
+    } else {
+        "dlmalloc".to_string()
+    }
+}
+
 // Memory management helper
 #[wasm_bindgen]
 pub fn get_memory_usage() -> u32 {
     // This is a simplified version - in practice you might want more detailed memory info
     std::mem::size_of::<ImageProcessor>() as u32
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_bytes(img: &DynamicImage) -> Vec<u8> {
+        let mut out = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut out), image::ImageFormat::Png)
+            .unwrap();
+        out
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        let crc = crc32fast::hash(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        chunk
+    }
+
+    fn png_options() -> ExportOptions {
+        ExportOptions {
+            format: "png".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn check_input_rejects_bad_headers_before_decoding() {
+        // A valid IHDR announcing 100000x100000 RGBA, with an empty IDAT.
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&100_000u32.to_be_bytes());
+        ihdr.extend_from_slice(&100_000u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        let mut bomb = b"\x89PNG\r\n\x1a\n".to_vec();
+        bomb.extend(png_chunk(b"IHDR", &ihdr));
+        bomb.extend(png_chunk(b"IDAT", &[]));
+        bomb.extend(png_chunk(b"IEND", &[]));
+
+        let err = decode::check_input(&bomb).unwrap_err();
+        assert!(err.contains("pixel limit"), "{}", err);
+        assert!(decode::check_input(b"not an image").is_err());
+        assert!(decode::check_input(&bomb[..12]).is_err());
+
+        let good = png_bytes(&DynamicImage::new_rgb8(4, 4));
+        assert!(decode::check_input(&good).is_ok());
+    }
+
+    #[test]
+    fn batch_survives_a_panicking_item() {
+        let good = png_bytes(&DynamicImage::new_rgb8(4, 4));
+        let options = png_options();
+        let outcomes: Vec<_> = (0..3)
+            .map(|index| {
+                if index == 1 {
+                    decode::tests::panic_on_next_decode();
+                }
+                export_item(&good, &options, None)
+            })
+            .collect();
+        assert!(outcomes[0].is_ok());
+        let err = outcomes[1].as_ref().err().unwrap();
+        assert!(err.contains("crafted decoder panic"), "{}", err);
+        assert!(outcomes[2].is_ok());
+    }
+
+    #[test]
+    fn png_palette_quantizes_to_256_colors() {
+        // 64×64 RGB gradient: 4096 distinct colors.
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(64, 64, |x, y| {
+            image::Rgb([(x * 4) as u8, (y * 4) as u8, 128])
+        }));
+        let options = ExportOptions {
+            png_palette: Some(true),
+            ..png_options()
+        };
+
+        let data = encode_image(img.clone(), 0, &options).unwrap().data.unwrap();
+        let chunks = metadata::png_chunk_list(&data);
+        assert_eq!(chunks[0].1[9], 3, "IHDR color type is indexed");
+        let palette = &chunks.iter().find(|(kind, _)| kind == b"PLTE").unwrap().1;
+        assert!(palette.len() / 3 <= 256);
+
+        let decoded = image::load_from_memory(&data).unwrap().to_rgb8();
+        let max_error = decoded
+            .pixels()
+            .zip(img.to_rgb8().pixels())
+            .flat_map(|(a, b)| (0..3).map(move |c| (a[c] as i16 - b[c] as i16).abs()))
+            .max()
+            .unwrap();
+        assert!(max_error <= 16, "max channel error {}", max_error);
+    }
+
+    #[test]
+    fn batch_reports_corrupt_items_without_failing_valid_ones() {
+        let png = png_bytes(&DynamicImage::new_rgb8(4, 4));
+        let mut jpeg = Vec::new();
+        DynamicImage::new_rgb8(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+        let buffers: [&[u8]; 5] = [&png, &png[..png.len() / 2], b"garbage", &[], &jpeg];
+        let options = png_options();
+
+        let items: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(index, bytes)| BatchItemResult::new(index as u32, export_item(bytes, &options, None)))
+            .collect();
+
+        let ok: Vec<_> = items.iter().map(|item| item.ok).collect();
+        assert_eq!(ok, [true, false, false, false, true]);
+        for item in &items {
+            assert_eq!(item.ok, item.result.is_some());
+            assert_eq!(item.ok, item.error.is_none());
+        }
+        assert!(items[4].result.as_ref().unwrap().data.is_some());
+    }
+
+    #[test]
+    fn palette_trns_transparency_survives_export() {
+        // 2×2 indexed PNG: entry 0 fully transparent, entry 1 opaque red.
+        let mut source = Vec::new();
+        let mut encoder = png::Encoder::new(&mut source, 2, 2);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(vec![0, 255, 0, 255, 0, 0]);
+        encoder.set_trns(vec![0]);
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&[0, 1, 1, 0]))
+            .unwrap();
+
+        let decoded = decode::decode_image(&source).unwrap().to_rgba8();
+        assert_eq!(decoded.get_pixel(0, 0)[3], 0);
+        assert_eq!(decoded.get_pixel(1, 0).0, [255, 0, 0, 255]);
+
+        for format in ["png", "webp"] {
+            for png_palette in [false, true] {
+                let options = ExportOptions {
+                    format: format.to_string(),
+                    png_palette: Some(png_palette),
+                    ..Default::default()
+                };
+                let data = export_bytes(&source, &options).unwrap().data.unwrap();
+                let out = image::load_from_memory(&data).unwrap().to_rgba8();
+                for (x, y) in [(0, 0), (1, 1)] {
+                    assert_eq!(out.get_pixel(x, y)[3], 0, "{} palette={}", format, png_palette);
+                }
+                assert_eq!(out.get_pixel(1, 0).0, [255, 0, 0, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn png_minimal_writes_only_critical_chunks() {
+        let source = png_bytes(&DynamicImage::ImageRgba8(image::RgbaImage::from_fn(16, 16, |x, y| {
+            image::Rgba([x as u8 * 16, y as u8 * 16, 128, 200])
+        })));
+        // Options that would otherwise add pHYs, iCCP, eXIf and iTXt.
+        let options = ExportOptions {
+            png_minimal: Some(true),
+            dpi: Some(300.0),
+            icc_profile: Some(vec![0; 128]),
+            inject_metadata: Some(HashMap::from([("Artist".to_string(), "x".to_string())])),
+            embed_provenance: Some(provenance::ProvenanceOptions {
+                signature: Some("test".to_string()),
+                metadata: Some(true),
+                lsb: None,
+            }),
+            ..png_options()
+        };
+        let data = export_bytes(&source, &options).unwrap().data.unwrap();
+
+        assert_eq!(&data[..8], b"\x89PNG\r\n\x1a\n");
+        let mut kinds = Vec::new();
+        let mut pos = 8;
+        while pos < data.len() {
+            let len = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+            let body = &data[pos + 4..pos + 8 + len];
+            let crc = u32::from_be_bytes(data[pos + 8 + len..pos + 12 + len].try_into().unwrap());
+            assert_eq!(crc32fast::hash(body), crc);
+            kinds.push(String::from_utf8(body[..4].to_vec()).unwrap());
+            pos += 12 + len;
+        }
+        assert_eq!(pos, data.len(), "no bytes after IEND");
+        kinds.dedup();
+        assert_eq!(kinds, ["IHDR", "IDAT", "IEND"]);
+    }
+    /// 16×8 JPEG, left half red and right half blue, tagged with EXIF
+    /// `orientation`.
+    fn oriented_jpeg(orientation: u16) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(image::RgbImage::from_fn(16, 8, |x, _| {
+            if x < 8 {
+                image::Rgb([255, 0, 0])
+            } else {
+                image::Rgb([0, 0, 255])
+            }
+        }));
+        let mut jpeg = Vec::new();
+        img.write_to(&mut std::io::Cursor::new(&mut jpeg), image::ImageFormat::Jpeg)
+            .unwrap();
+
+        // Big-endian TIFF with one IFD0 entry: Orientation, SHORT, 1 value.
+        let mut tiff = b"MM\0\x2a\0\0\0\x08\0\x01\x01\x12\0\x03\0\0\0\x01".to_vec();
+        tiff.extend_from_slice(&orientation.to_be_bytes());
+        tiff.extend_from_slice(&[0; 6]);
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((2 + metadata::EXIF_HEADER.len() + tiff.len()) as u16).to_be_bytes());
+        app1.extend_from_slice(metadata::EXIF_HEADER);
+        app1.extend_from_slice(&tiff);
+        jpeg.splice(2..2, app1);
+        jpeg
+    }
+
+    #[test]
+    fn orientation_override_beats_exif() {
+        let jpeg = oriented_jpeg(6);
+        assert_eq!(metadata::exif_orientation(&jpeg), Some(6));
+
+        let export = |override_value: Option<u8>, apply_exif: Option<bool>| {
+            let options = ExportOptions {
+                orientation_override: override_value,
+                apply_exif_orientation: apply_exif,
+                ..png_options()
+            };
+            let data = export_bytes(&jpeg, &options).unwrap().data.unwrap();
+            image::load_from_memory(&data).unwrap().to_rgb8()
+        };
+        let is_red = |img: &image::RgbImage, x, y| img.get_pixel(x, y)[0] > 200;
+
+        // Stored pixels: no option applies no orientation.
+        let plain = export(None, None);
+        assert_eq!(plain.dimensions(), (16, 8));
+        assert!(is_red(&plain, 0, 0));
+
+        // EXIF 6 rotates 90° clockwise: the red left half ends up on top.
+        let exif = export(None, Some(true));
+        assert_eq!(exif.dimensions(), (8, 16));
+        assert!(is_red(&exif, 4, 0) && !is_red(&exif, 4, 15));
+
+        // The override replaces EXIF 6: 180° puts red on the right.
+        let overridden = export(Some(3), Some(true));
+        assert_eq!(overridden.dimensions(), (16, 8));
+        assert!(is_red(&overridden, 15, 4) && !is_red(&overridden, 0, 4));
+    }
+
+    #[test]
+    fn heif_exif_orientation_is_ignored() {
+        // HEIF container rotation (irot/imir) is applied by libheif at decode
+        // and wins over EXIF, so EXIF is never read from a HEIF buffer, even
+        // one that carries an EXIF Orientation.
+        let mut heif = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic".to_vec();
+        heif.extend_from_slice(&oriented_jpeg(6));
+        assert!(decode::is_heif(&heif));
+        assert_eq!(metadata::exif_orientation(&heif), None);
+    }
+
+    #[test]
+    fn auto_skips_candidates_that_fail_to_encode() {
+        let good = png_bytes(&DynamicImage::new_rgb8(4, 4));
+        let auto = |candidates: &[&str]| ExportOptions {
+            format: "auto".to_string(),
+            auto_formats: Some(candidates.iter().map(|c| c.to_string()).collect()),
+            ..Default::default()
+        };
+
+        let result = export_item(&good, &auto(&["bmp", "png"]), None).unwrap();
+        assert_eq!(result.format.as_deref(), Some("png"));
+
+        let err = export_item(&good, &auto(&["bmp", "tga"]), None).unwrap_err();
+        assert!(err.contains("tga: Unsupported format"), "{}", err);
+    }
+
+    #[test]
+    fn memory_budget_rejects_an_oversized_pad_canvas() {
+        let good = png_bytes(&DynamicImage::new_rgb8(4, 4));
+        let padded = |budget| ExportOptions {
+            max_width: Some(64),
+            max_height: Some(64),
+            pad_to_exact: Some(true),
+            memory_budget: Some(budget),
+            ..png_options()
+        };
+
+        let err = export_item(&good, &padded(64 * 64 * 4 - 1), None).unwrap_err();
+        assert!(err.contains("pad_to_exact canvas"), "{}", err);
+
+        let result = export_item(&good, &padded(64 * 64 * 4), None).unwrap();
+        assert_eq!((result.width, result.height), (64, 64));
+    }
+}
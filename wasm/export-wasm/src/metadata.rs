@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+use std::io::Cursor;
+
+use exif::{Error as ExifError, In, Reader};
+
+/// Read the primary-image EXIF fields of an encoded image into a
+/// tag name → display value map.
+///
+/// Returns an empty map when the container carries no EXIF block.
+pub fn read_exif_fields(buffer: &[u8]) -> Result<BTreeMap<String, String>, String> {
+    let exif = match Reader::new().read_from_container(&mut Cursor::new(buffer)) {
+        Ok(exif) => exif,
+        Err(ExifError::NotFound(_)) => return Ok(BTreeMap::new()),
+        Err(e) => return Err(format!("EXIF read error: {}", e)),
+    };
+
+    let mut fields = BTreeMap::new();
+    for field in exif.fields() {
+        // The thumbnail IFD repeats tags like Orientation for the embedded
+        // preview; only the primary image is interesting to callers.
+        if field.ifd_num != In::PRIMARY {
+            continue;
+        }
+        fields.insert(
+            field.tag.to_string(),
+            field.display_value().with_unit(&exif).to_string(),
+        );
+    }
+
+    Ok(fields)
+}
+
+/// The primary-image EXIF Orientation (1-8) of an encoded image, if it has
+/// a valid one. HEIF is skipped: its container rotation and mirroring take
+/// precedence, and libheif has already applied them when decoding.
+pub fn exif_orientation(buffer: &[u8]) -> Option<u8> {
+    if crate::decode::is_heif(buffer) {
+        return None;
+    }
+    let exif = Reader::new().read_from_container(&mut Cursor::new(buffer)).ok()?;
+    let value = exif
+        .get_field(exif::Tag::Orientation, In::PRIMARY)?
+        .value
+        .get_uint(0)?;
+    u8::try_from(value).ok().filter(|v| (1..=8).contains(v))
+}
+
+/// Prefix of an APP1 segment carrying EXIF.
+pub const EXIF_HEADER: &[u8] = b"Exif\0\0";
+/// Prefix of an APP1 segment carrying XMP.
+pub const XMP_NAMESPACE: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Keyword of the iTXt chunk carrying XMP in PNG.
+pub const XMP_PNG_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Largest APP segment payload: the length field is 16 bits and counts
+/// itself.
+const MAX_APP_PAYLOAD: usize = 65533;
+
+/// EXIF IFD0 ASCII tags the `inject_metadata` export option can write.
+pub const INJECTABLE_TAGS: [(&str, u16); 4] = [
+    ("ImageDescription", 0x010E),
+    ("Software", 0x0131),
+    ("Artist", 0x013B),
+    ("Copyright", 0x8298),
+];
+
+/// Tag number of an `INJECTABLE_TAGS` key.
+pub fn injectable_tag(key: &str) -> Option<u16> {
+    INJECTABLE_TAGS
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|&(_, tag)| tag)
+}
+
+/// Big-endian TIFF structure for an EXIF block: IFD0 with the ASCII
+/// `fields` (tag, value), and when `thumbnail` (a baseline JPEG) is given an
+/// IFD1 pointing at it as the preview.
+pub fn build_exif(fields: &[(u16, String)], thumbnail: Option<&[u8]>) -> Vec<u8> {
+    let mut fields = fields.to_vec();
+    fields.sort_by_key(|&(tag, _)| tag);
+
+    // Header, IFD0 entries, then the values too long for their 4-byte slot.
+    let ifd0_end = 8 + 2 + 12 * fields.len() + 4;
+    let mut values = Vec::new();
+    let mut entries = Vec::with_capacity(12 * fields.len());
+    for (tag, value) in &fields {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        entries.extend_from_slice(&tag.to_be_bytes());
+        entries.extend_from_slice(&2u16.to_be_bytes());
+        entries.extend_from_slice(&(bytes.len() as u32).to_be_bytes());
+        if bytes.len() <= 4 {
+            bytes.resize(4, 0);
+            entries.extend_from_slice(&bytes);
+        } else {
+            entries.extend_from_slice(&((ifd0_end + values.len()) as u32).to_be_bytes());
+            values.extend_from_slice(&bytes);
+            // Offsets must be even.
+            if values.len() % 2 == 1 {
+                values.push(0);
+            }
+        }
+    }
+    let ifd1_offset = (ifd0_end + values.len()) as u32;
+
+    let mut tiff = Vec::with_capacity(ifd1_offset as usize + 42 + thumbnail.map_or(0, <[u8]>::len));
+    tiff.extend_from_slice(b"MM\0\x2A");
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&(fields.len() as u16).to_be_bytes());
+    tiff.extend_from_slice(&entries);
+    tiff.extend_from_slice(&(if thumbnail.is_some() { ifd1_offset } else { 0 }).to_be_bytes());
+    tiff.extend_from_slice(&values);
+
+    if let Some(thumbnail) = thumbnail {
+        // Three entries and the next-IFD link, then the thumbnail itself.
+        let thumbnail_offset = ifd1_offset + 2 + 3 * 12 + 4;
+        tiff.extend_from_slice(&3u16.to_be_bytes());
+        // Compression = 6 (JPEG), SHORT value left-justified in the 4-byte slot.
+        tiff.extend_from_slice(&[0x01, 0x03, 0x00, 0x03, 0, 0, 0, 1, 0x00, 0x06, 0, 0]);
+        // JPEGInterchangeFormat and JPEGInterchangeFormatLength, both LONG.
+        tiff.extend_from_slice(&[0x02, 0x01, 0x00, 0x04, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&thumbnail_offset.to_be_bytes());
+        tiff.extend_from_slice(&[0x02, 0x02, 0x00, 0x04, 0, 0, 0, 1]);
+        tiff.extend_from_slice(&(thumbnail.len() as u32).to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        tiff.extend_from_slice(thumbnail);
+    }
+    tiff
+}
+
+/// Insert an EXIF APP1 segment built by `build_exif` into `jpeg`, directly
+/// after SOI and any JFIF APP0 segment.
+///
+/// Only for freshly encoded output that has no EXIF block of its own.
+pub fn embed_exif(
+    jpeg: &[u8],
+    fields: &[(u16, String)],
+    thumbnail: Option<&[u8]>,
+) -> Result<Vec<u8>, String> {
+    let mut payload = EXIF_HEADER.to_vec();
+    payload.extend_from_slice(&build_exif(fields, thumbnail));
+    if payload.len() > MAX_APP_PAYLOAD {
+        return Err(format!(
+            "EXIF block of {} bytes does not fit in an APP1 segment",
+            payload.len()
+        ));
+    }
+    insert_app_segment(jpeg, 0xE1, &payload)
+}
+
+/// Insert an APPn segment with `payload` directly after SOI and any JFIF
+/// APP0 segment of `jpeg`.
+pub fn insert_app_segment(jpeg: &[u8], marker: u8, payload: &[u8]) -> Result<Vec<u8>, String> {
+    if jpeg.get(..2) != Some(&[0xFF, 0xD8][..]) {
+        return Err("Not a JPEG stream".to_string());
+    }
+    if payload.len() > MAX_APP_PAYLOAD {
+        return Err(format!("Segment payload of {} bytes is too large", payload.len()));
+    }
+
+    // Keep a leading JFIF APP0 first, as JFIF readers expect.
+    let mut insert_at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0][..]) {
+        let app0_len = jpeg
+            .get(4..6)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]) as usize)
+            .ok_or("Truncated APP0 segment")?;
+        insert_at += 2 + app0_len;
+    }
+    if insert_at > jpeg.len() {
+        return Err("Truncated APP0 segment".to_string());
+    }
+
+    let mut out = Vec::with_capacity(jpeg.len() + 4 + payload.len());
+    out.extend_from_slice(&jpeg[..insert_at]);
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+    out.extend_from_slice(&jpeg[insert_at..]);
+    Ok(out)
+}
+
+/// Payloads of the APPn segments with `marker` that come before the image
+/// data of `jpeg`.
+pub fn app_segments(jpeg: &[u8], marker: u8) -> Vec<&[u8]> {
+    let mut segments = Vec::new();
+    if jpeg.get(..2) != Some(&[0xFF, 0xD8][..]) {
+        return segments;
+    }
+
+    let mut pos = 2;
+    while let Some(&[0xFF, kind, hi, lo]) = jpeg.get(pos..pos + 4) {
+        // Start of scan: entropy-coded data follows, no more headers.
+        if kind == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([hi, lo]) as usize;
+        let Some(payload) = jpeg.get(pos + 4..pos + 2 + len) else {
+            break;
+        };
+        if kind == marker {
+            segments.push(payload);
+        }
+        pos += 2 + len;
+    }
+    segments
+}
+
+/// Copy of `jpeg` without the APPn segments with `marker` whose payload
+/// matches `drop`. Segments after the start of scan are left alone.
+pub fn remove_app_segments(jpeg: &[u8], marker: u8, drop: impl Fn(&[u8]) -> bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(jpeg.len());
+    if jpeg.get(..2) != Some(&[0xFF, 0xD8][..]) {
+        out.extend_from_slice(jpeg);
+        return out;
+    }
+
+    out.extend_from_slice(&jpeg[..2]);
+    let mut pos = 2;
+    while let Some(&[0xFF, kind, hi, lo]) = jpeg.get(pos..pos + 4) {
+        if kind == 0xDA {
+            break;
+        }
+        let end = pos + 2 + u16::from_be_bytes([hi, lo]) as usize;
+        let Some(payload) = jpeg.get(pos + 4..end) else {
+            break;
+        };
+        if !(kind == marker && drop(payload)) {
+            out.extend_from_slice(&jpeg[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&jpeg[pos..]);
+    out
+}
+
+/// Move the EXIF and XMP of `source` into `processed`, replacing any that
+/// `processed` already has. Both must be `format`, "jpeg" or "png"; other
+/// metadata on either side is left as it is.
+pub fn transplant(format: &str, processed: &[u8], source: &[u8]) -> Result<Vec<u8>, String> {
+    match format {
+        "jpeg" => {
+            let carried = |payload: &[u8]| {
+                payload.starts_with(EXIF_HEADER) || payload.starts_with(XMP_NAMESPACE)
+            };
+            let mut out = remove_app_segments(processed, 0xE1, carried);
+            // Each insert lands right after SOI/APP0, so go backwards to keep
+            // the source order.
+            for payload in app_segments(source, 0xE1).into_iter().rev().filter(|p| carried(p)) {
+                out = insert_app_segment(&out, 0xE1, payload)?;
+            }
+            Ok(out)
+        }
+        "png" => {
+            let carried = |kind: &[u8; 4], data: &[u8]| {
+                kind == b"eXIf" || (kind == b"iTXt" && data.starts_with(XMP_PNG_KEYWORD))
+            };
+            let mut out = remove_png_chunks(processed, carried);
+            for (kind, data) in png_chunk_list(source).into_iter().rev() {
+                if carried(&kind, data) {
+                    out = insert_png_chunk(&out, &kind, data)?;
+                }
+            }
+            Ok(out)
+        }
+        _ => Err(format!("Cannot transplant metadata into {}", format)),
+    }
+}
+
+/// Tag `data` as `dpi` dots per inch on both axes: the JFIF APP0 density
+/// for JPEG, a pHYs chunk for PNG. Any existing tag is replaced.
+pub fn set_dpi(format: &str, data: &[u8], dpi: f32) -> Result<Vec<u8>, String> {
+    match format {
+        "jpeg" => {
+            let density = (dpi.round() as u32).clamp(1, u16::MAX as u32) as u16;
+            let mut payload = b"JFIF\0".to_vec();
+            // Version 1.02, units of dots per inch, then X and Y density and
+            // no JFIF thumbnail.
+            payload.extend_from_slice(&[1, 2, 1]);
+            payload.extend_from_slice(&density.to_be_bytes());
+            payload.extend_from_slice(&density.to_be_bytes());
+            payload.extend_from_slice(&[0, 0]);
+            let out = remove_app_segments(data, 0xE0, |p| p.starts_with(b"JFIF\0"));
+            insert_app_segment(&out, 0xE0, &payload)
+        }
+        "png" => {
+            let per_meter = (dpi / 0.0254).round() as u32;
+            let mut phys = Vec::with_capacity(9);
+            phys.extend_from_slice(&per_meter.to_be_bytes());
+            phys.extend_from_slice(&per_meter.to_be_bytes());
+            // Unit: meter.
+            phys.push(1);
+            let out = remove_png_chunks(data, |kind, _| kind == b"pHYs");
+            insert_png_chunk(&out, b"pHYs", &phys)
+        }
+        _ => Err(format!("{} has no DPI field", format)),
+    }
+}
+
+/// Prefix of an APP2 segment carrying part of an ICC profile.
+const ICC_JPEG_HEADER: &[u8] = b"ICC_PROFILE\0";
+
+/// Whether `profile` looks like an ICC profile: a full 128-byte header and
+/// tag count with the `acsp` signature.
+pub fn is_icc_profile(profile: &[u8]) -> bool {
+    profile.len() >= 132 && profile.get(36..40) == Some(&b"acsp"[..])
+}
+
+/// Embed `profile` in `data`, replacing any profile it already carries:
+/// APP2 segments for JPEG, iCCP for PNG (dropping sRGB, which conflicts
+/// with it) and an ICCP chunk for WebP, which is moved to the extended
+/// format if needed.
+pub fn embed_icc(format: &str, data: &[u8], profile: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_icc_profile(profile) {
+        return Err("Not an ICC profile".to_string());
+    }
+    match format {
+        "jpeg" => {
+            // Each segment carries a 1-based sequence number and the count.
+            let parts: Vec<&[u8]> = profile
+                .chunks(MAX_APP_PAYLOAD - ICC_JPEG_HEADER.len() - 2)
+                .collect();
+            let count = u8::try_from(parts.len())
+                .map_err(|_| format!("ICC profile of {} bytes is too large", profile.len()))?;
+            let mut out = remove_app_segments(data, 0xE2, |p| p.starts_with(ICC_JPEG_HEADER));
+            for (index, part) in parts.iter().enumerate().rev() {
+                let mut payload = ICC_JPEG_HEADER.to_vec();
+                payload.extend_from_slice(&[index as u8 + 1, count]);
+                payload.extend_from_slice(part);
+                out = insert_app_segment(&out, 0xE2, &payload)?;
+            }
+            Ok(out)
+        }
+        "png" => {
+            // Keyword, NUL, zlib compression method, compressed profile.
+            let mut chunk = b"ICC Profile\0\0".to_vec();
+            chunk.extend_from_slice(&fdeflate::compress_to_vec(profile));
+            let out = remove_png_chunks(data, |kind, _| kind == b"iCCP" || kind == b"sRGB");
+            insert_png_chunk(&out, b"iCCP", &chunk)
+        }
+        "webp" => embed_webp_icc(data, profile),
+        _ => Err(format!("No ICC profile carrier for {}", format)),
+    }
+}
+
+/// Rebuild a WebP with an ICCP chunk right after VP8X, creating VP8X from
+/// the bitstream header for a simple-format file.
+fn embed_webp_icc(webp: &[u8], profile: &[u8]) -> Result<Vec<u8>, String> {
+    if webp.get(..4) != Some(&b"RIFF"[..]) || webp.get(8..12) != Some(&b"WEBP"[..]) {
+        return Err("Not a WebP stream".to_string());
+    }
+
+    let mut chunks = Vec::new();
+    let mut pos = 12;
+    while let Some(header) = webp.get(pos..pos + 8) {
+        let len = u32::from_le_bytes([header[4], header[5], header[6], header[7]]) as usize;
+        let data = webp
+            .get(pos + 8..pos + 8 + len)
+            .ok_or("Truncated WebP chunk")?;
+        chunks.push(([header[0], header[1], header[2], header[3]], data));
+        pos += 8 + len + (len & 1);
+    }
+
+    let (kind, data) = chunks.first().ok_or("WebP stream has no chunks")?;
+    let mut vp8x = if kind == b"VP8X" {
+        data.to_vec()
+    } else {
+        let (width, height, alpha) = webp_frame_info(kind, data)?;
+        let mut vp8x = vec![if alpha { 0x10 } else { 0 }, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        vp8x
+    };
+    // ICC profile flag.
+    vp8x[0] |= 0x20;
+
+    let mut body = b"WEBP".to_vec();
+    push_riff_chunk(&mut body, b"VP8X", &vp8x);
+    push_riff_chunk(&mut body, b"ICCP", profile);
+    for (kind, data) in &chunks {
+        if kind != b"VP8X" && kind != b"ICCP" {
+            push_riff_chunk(&mut body, kind, data);
+        }
+    }
+
+    let size = u32::try_from(body.len()).map_err(|_| "WebP is too large".to_string())?;
+    let mut out = Vec::with_capacity(8 + body.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&size.to_le_bytes());
+    out.extend_from_slice(&body);
+    Ok(out)
+}
+
+/// Canvas width, height and whether alpha is used, from a VP8L or VP8
+/// bitstream header.
+fn webp_frame_info(kind: &[u8; 4], data: &[u8]) -> Result<(u32, u32, bool), String> {
+    match kind {
+        b"VP8L" if data.len() >= 5 && data[0] == 0x2F => {
+            let bits = u32::from_le_bytes([data[1], data[2], data[3], data[4]]);
+            Ok(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1, bits & (1 << 28) != 0))
+        }
+        b"VP8 " if data.len() >= 10 => {
+            let width = u16::from_le_bytes([data[6], data[7]]) & 0x3FFF;
+            let height = u16::from_le_bytes([data[8], data[9]]) & 0x3FFF;
+            Ok((width as u32, height as u32, false))
+        }
+        _ => Err("Unrecognized WebP bitstream".to_string()),
+    }
+}
+
+fn push_riff_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+/// Insert a chunk into `png` directly after IHDR.
+pub fn insert_png_chunk(png: &[u8], kind: &[u8; 4], data: &[u8]) -> Result<Vec<u8>, String> {
+    // 8-byte signature, then IHDR: length, type, 13 data bytes, CRC.
+    const AFTER_IHDR: usize = 8 + 4 + 4 + 13 + 4;
+    if png.get(12..16) != Some(&b"IHDR"[..]) || png.len() < AFTER_IHDR {
+        return Err("Not a PNG stream".to_string());
+    }
+
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+
+    let mut out = Vec::with_capacity(png.len() + 12 + data.len());
+    out.extend_from_slice(&png[..AFTER_IHDR]);
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    out.extend_from_slice(&crc.finalize().to_be_bytes());
+    out.extend_from_slice(&png[AFTER_IHDR..]);
+    Ok(out)
+}
+
+/// Data of the chunks of type `kind` in `png`.
+pub fn png_chunks<'a>(png: &'a [u8], kind: &[u8; 4]) -> Vec<&'a [u8]> {
+    png_chunk_list(png)
+        .into_iter()
+        .filter(|(chunk_kind, _)| chunk_kind == kind)
+        .map(|(_, data)| data)
+        .collect()
+}
+
+/// Copy of `png` without the chunks for which `drop(kind, data)` holds.
+/// Anything after the first truncated chunk is kept as is.
+pub fn remove_png_chunks(png: &[u8], drop: impl Fn(&[u8; 4], &[u8]) -> bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(png.len());
+    if png.get(..8) != Some(&b"\x89PNG\r\n\x1a\n"[..]) {
+        out.extend_from_slice(png);
+        return out;
+    }
+
+    out.extend_from_slice(&png[..8]);
+    let mut pos = 8;
+    while let Some(header) = png.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let Some(data) = png.get(pos + 8..pos + 8 + len) else {
+            break;
+        };
+        let end = (pos + 12 + len).min(png.len());
+        if !drop(&kind, data) {
+            out.extend_from_slice(&png[pos..end]);
+        }
+        pos = end;
+    }
+    out.extend_from_slice(&png[pos..]);
+    out
+}
+
+/// Type and data of every chunk in `png`, in file order. Stops at the first
+/// truncated chunk.
+pub fn png_chunk_list(png: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut chunks = Vec::new();
+    if png.get(..8) != Some(&b"\x89PNG\r\n\x1a\n"[..]) {
+        return chunks;
+    }
+
+    let mut pos = 8;
+    while let Some(header) = png.get(pos..pos + 8) {
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        let Some(data) = png.get(pos + 8..pos + 8 + len) else {
+            break;
+        };
+        chunks.push(([header[4], header[5], header[6], header[7]], data));
+        pos += 12 + len;
+    }
+    chunks
+}
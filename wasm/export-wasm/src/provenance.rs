@@ -0,0 +1,158 @@
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+
+use crate::metadata::{self, XMP_NAMESPACE, XMP_PNG_KEYWORD};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ProvenanceOptions {
+    pub signature: Option<String>, // defaults to "blake3:" + hash of the exported pixels
+    pub metadata: Option<bool>,    // XMP dc:identifier in JPEG/PNG output, default true
+    pub lsb: Option<bool>,         // hide in blue-channel LSBs; lossless output only
+}
+
+/// What `read_provenance` found in each carrier.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct Provenance {
+    pub metadata: Option<String>,
+    pub lsb: Option<String>,
+}
+
+/// Marks the start of an LSB payload so arbitrary images read as empty.
+const LSB_MAGIC: &[u8; 4] = b"LPV1";
+/// Magic plus a big-endian u16 length.
+const LSB_HEADER: usize = 6;
+
+/// BLAKE3 of the RGBA8 pixels plus dimensions, taken before any LSB
+/// embedding. Re-hashing an LSB-marked export therefore gives a different
+/// value; the signature identifies the content that was exported.
+pub fn default_signature(img: &DynamicImage) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&img.width().to_le_bytes());
+    hasher.update(&img.height().to_le_bytes());
+    hasher.update(img.to_rgba8().as_raw());
+    format!("blake3:{}", hasher.finalize().to_hex())
+}
+
+/// Write `signature` into the least significant bit of the blue channel,
+/// one bit per pixel in row-major order, behind a magic and length header.
+pub fn embed_lsb(img: &DynamicImage, signature: &str) -> Result<DynamicImage, String> {
+    let bytes = signature.as_bytes();
+    let len = u16::try_from(bytes.len()).map_err(|_| "Signature is too long".to_string())?;
+    let mut payload = Vec::with_capacity(LSB_HEADER + bytes.len());
+    payload.extend_from_slice(LSB_MAGIC);
+    payload.extend_from_slice(&len.to_be_bytes());
+    payload.extend_from_slice(bytes);
+
+    let (width, height) = img.dimensions();
+    if payload.len() as u64 * 8 > width as u64 * height as u64 {
+        return Err(format!(
+            "Image of {}x{} is too small to hold a {}-byte signature",
+            width,
+            height,
+            bytes.len()
+        ));
+    }
+
+    let mut rgba = img.to_rgba8();
+    let bits = payload
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1));
+    for (pixel, bit) in rgba.pixels_mut().zip(bits) {
+        pixel[2] = (pixel[2] & !1) | bit;
+    }
+
+    Ok(if img.color().has_alpha() {
+        DynamicImage::ImageRgba8(rgba)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+    })
+}
+
+/// Read a signature written by `embed_lsb`, or `None` when the magic is
+/// missing or the payload is cut off.
+pub fn read_lsb(img: &DynamicImage) -> Option<String> {
+    let rgba = img.to_rgba8();
+    let mut bits = rgba.pixels().map(|p| p[2] & 1);
+    let mut next_byte = || {
+        (0..8).try_fold(0u8, |byte, _| bits.next().map(|bit| (byte << 1) | bit))
+    };
+
+    let header: Vec<u8> = (0..LSB_HEADER).map_while(|_| next_byte()).collect();
+    if header.len() != LSB_HEADER || &header[..4] != LSB_MAGIC {
+        return None;
+    }
+    let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+    let bytes: Vec<u8> = (0..len).map_while(|_| next_byte()).collect();
+    if bytes.len() != len {
+        return None;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Add an XMP packet carrying `signature` as `dc:identifier` to encoded
+/// JPEG or PNG output. Other formats have no carrier here.
+pub fn embed_metadata(format: &str, data: &[u8], signature: &str) -> Result<Vec<u8>, String> {
+    let packet = xmp_packet(signature);
+    match format {
+        "jpeg" => {
+            let mut payload = XMP_NAMESPACE.to_vec();
+            payload.extend_from_slice(packet.as_bytes());
+            metadata::insert_app_segment(data, 0xE1, &payload)
+        }
+        "png" => {
+            // iTXt: keyword, NUL, uncompressed, no language or translation.
+            let mut chunk = XMP_PNG_KEYWORD.to_vec();
+            chunk.extend_from_slice(&[0, 0, 0, 0, 0]);
+            chunk.extend_from_slice(packet.as_bytes());
+            metadata::insert_png_chunk(data, b"iTXt", &chunk)
+        }
+        _ => Err(format!("No metadata carrier for {}", format)),
+    }
+}
+
+/// Signature from the XMP `dc:identifier` of a JPEG or PNG, if any.
+pub fn read_metadata(buffer: &[u8]) -> Option<String> {
+    let jpeg_packets = metadata::app_segments(buffer, 0xE1)
+        .into_iter()
+        .filter_map(|segment| segment.strip_prefix(XMP_NAMESPACE));
+    let png_packets = metadata::png_chunks(buffer, b"iTXt")
+        .into_iter()
+        .filter_map(|chunk| chunk.strip_prefix(XMP_PNG_KEYWORD))
+        .filter_map(|rest| rest.get(5..));
+
+    jpeg_packets
+        .chain(png_packets)
+        .filter_map(|packet| std::str::from_utf8(packet).ok())
+        .find_map(|packet| {
+            let start = packet.find("<dc:identifier>")? + "<dc:identifier>".len();
+            let end = start + packet[start..].find("</dc:identifier>")?;
+            Some(xml_unescape(&packet[start..end]))
+        })
+}
+
+fn xmp_packet(signature: &str) -> String {
+    format!(
+        concat!(
+            "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">",
+            "<dc:identifier>{}</dc:identifier>",
+            "</rdf:Description></rdf:RDF></x:xmpmeta>",
+            "<?xpacket end=\"r\"?>"
+        ),
+        xml_escape(signature)
+    )
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn xml_unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
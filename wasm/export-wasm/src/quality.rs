@@ -0,0 +1,234 @@
+use image::{
+    codecs::jpeg::JpegEncoder, codecs::png::CompressionType, imageops::FilterType, DynamicImage,
+    GrayImage,
+};
+
+use crate::presets;
+use crate::utils::Deadline;
+
+/// Edge length of the luma proxy used to probe JPEG quality.
+const PROBE_SIZE: u32 = 256;
+/// Mean SSIM the probe encode must reach before a quality is accepted.
+const TARGET_SSIM: f64 = 0.98;
+const MIN_SMART_QUALITY: u8 = 50;
+const MAX_SMART_QUALITY: u8 = 95;
+
+/// Largest increase `adaptive_jpeg_quality` applies.
+const ADAPTIVE_MAX_BOOST: f32 = 0.1;
+/// Linear scale at and below which the full boost applies.
+const ADAPTIVE_FULL_BOOST_SCALE: f32 = 0.25;
+/// `adaptive_jpeg_quality` never raises quality past this.
+const ADAPTIVE_MAX_QUALITY: f32 = 0.95;
+
+/// JPEG quality for an image downscaled to `scale` (output width over
+/// source width) from one that would have used `quality`.
+///
+/// The boost grows linearly from 0 at scale 1.0 to 0.1 at a quarter size
+/// or smaller, so a 50% downscale adds about 0.067. A downscale removes
+/// detail, and artifacts show more in what is left. The result is capped at
+/// 0.95 and is never lower than `quality`.
+pub fn adaptive_jpeg_quality(quality: f32, scale: f32) -> f32 {
+    let shrink = ((1.0 - scale) / (1.0 - ADAPTIVE_FULL_BOOST_SCALE)).clamp(0.0, 1.0);
+    (quality + ADAPTIVE_MAX_BOOST * shrink)
+        .min(ADAPTIVE_MAX_QUALITY)
+        .max(quality)
+}
+
+/// Pick a JPEG quality (0.0–1.0 scale, like `ExportOptions::quality`) for
+/// this image.
+///
+/// This is a heuristic: a downscaled luma copy is encoded at trial qualities
+/// and the lowest one whose decode reaches `TARGET_SSIM` against the copy
+/// wins. Busy images need a higher quality to hold the target, flat ones get
+/// away with less, which evens out perceived quality across an album.
+///
+/// Once `deadline` expires the search stops and returns the upper bound,
+/// the lowest quality not yet ruled out as too low.
+pub fn smart_jpeg_quality(img: &DynamicImage, deadline: Deadline) -> Result<f32, String> {
+    let probe = img
+        .resize(PROBE_SIZE, PROBE_SIZE, FilterType::Triangle)
+        .to_luma8();
+
+    let (mut lo, mut hi) = (MIN_SMART_QUALITY, MAX_SMART_QUALITY);
+    while lo < hi && !deadline.expired() {
+        let mid = lo + (hi - lo) / 2;
+        if probe_ssim(&probe, mid)? >= TARGET_SSIM {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    Ok(hi as f32 / 100.0)
+}
+
+fn probe_ssim(probe: &GrayImage, quality: u8) -> Result<f64, String> {
+    let mut encoded = Vec::new();
+    JpegEncoder::new_with_quality(&mut encoded, quality)
+        .encode_image(probe)
+        .map_err(|e| format!("JPEG encoding error: {}", e))?;
+    let decoded = image::load_from_memory(&encoded)
+        .map_err(|e| format!("JPEG decoding error: {}", e))?
+        .to_luma8();
+    Ok(ssim(probe, &decoded))
+}
+
+/// Mean SSIM over non-overlapping 8×8 windows of two equally sized luma
+/// images.
+pub fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+    const WINDOW: u32 = 8;
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+
+    let (width, height) = a.dimensions();
+    let mut total = 0.0;
+    let mut windows = 0u32;
+
+    for wy in (0..height).step_by(WINDOW as usize) {
+        for wx in (0..width).step_by(WINDOW as usize) {
+            let (w, h) = (WINDOW.min(width - wx), WINDOW.min(height - wy));
+            let n = (w * h) as f64;
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) =
+                (0.0, 0.0, 0.0, 0.0, 0.0);
+            for y in wy..wy + h {
+                for x in wx..wx + w {
+                    let pa = a.get_pixel(x, y)[0] as f64;
+                    let pb = b.get_pixel(x, y)[0] as f64;
+                    sum_a += pa;
+                    sum_b += pb;
+                    sum_aa += pa * pa;
+                    sum_bb += pb * pb;
+                    sum_ab += pa * pb;
+                }
+            }
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let cov = sum_ab / n - mean_a * mean_b;
+
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+        }
+    }
+
+    if windows == 0 {
+        1.0
+    } else {
+        total / windows as f64
+    }
+}
+
+/// Encoder settings a `quality_preset` stands for.
+///
+/// | preset | JPEG quality | PNG compression |
+/// |--------|--------------|-----------------|
+/// | low    | 0.60         | fast            |
+/// | medium | 0.75         | default         |
+/// | high   | 0.90         | best            |
+/// | max    | 1.00         | best            |
+///
+/// WebP output is lossless in every preset, the only mode the encoder has.
+/// JPEG chroma subsampling is fixed by the encoder and not part of a preset.
+/// The JPEG qualities come from `wasm/shared/presets.rs`, which
+/// thumbnail-wasm uses for its own `quality_preset`.
+#[derive(Debug, Clone, Copy)]
+pub struct Preset {
+    pub jpeg_quality: f32,
+    pub png_compression: CompressionType,
+}
+
+pub fn preset(name: &str) -> Option<Preset> {
+    let jpeg_quality = presets::jpeg_quality(name)? as f32 / 100.0;
+    let png_compression = match name.to_lowercase().as_str() {
+        "low" => CompressionType::Fast,
+        "medium" => CompressionType::Default,
+        _ => CompressionType::Best,
+    };
+    Some(Preset {
+        jpeg_quality,
+        png_compression,
+    })
+}
+
+/// IJG (libjpeg) luminance quantization table at quality 50, natural order.
+const IJG_LUMA_TABLE: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69,
+    56, 14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104,
+    113, 92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+/// Natural-order index of each coefficient in zigzag order, the order DQT
+/// segments store tables in.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// Estimate the 1–100 quality a JPEG was saved at from its luminance
+/// quantization table: the IJG quality whose scaled standard table is
+/// closest to it. Exact for libjpeg-family encoders; for encoders with their
+/// own tables (some cameras, Photoshop) it is the nearest IJG equivalent.
+pub fn estimate_jpeg_quality(jpeg: &[u8]) -> Result<u8, String> {
+    let table = luma_quant_table(jpeg)?;
+    (1..=100u32)
+        .min_by_key(|&quality| {
+            let scale = if quality < 50 { 5000 / quality } else { 200 - quality * 2 };
+            (0..64)
+                .map(|k| {
+                    let natural = ZIGZAG[k];
+                    let expected =
+                        ((IJG_LUMA_TABLE[natural] as u32 * scale + 50) / 100).clamp(1, 255);
+                    (expected as i64 - table[k] as i64).unsigned_abs()
+                })
+                .sum::<u64>()
+        })
+        .map(|quality| quality as u8)
+        .ok_or_else(|| "No quality candidate".to_string())
+}
+
+/// Quantization table 0 (luminance by convention) in zigzag order.
+fn luma_quant_table(jpeg: &[u8]) -> Result<[u16; 64], String> {
+    if jpeg.get(..2) != Some(&[0xFF, 0xD8][..]) {
+        return Err("Not a JPEG stream".to_string());
+    }
+
+    let mut pos = 2;
+    while let Some(&[0xFF, marker, hi, lo]) = jpeg.get(pos..pos + 4) {
+        if marker == 0xDA {
+            break;
+        }
+        let len = u16::from_be_bytes([hi, lo]) as usize;
+        let segment = jpeg
+            .get(pos + 4..pos + 2 + len)
+            .ok_or("Truncated JPEG segment")?;
+        if marker == 0xDB {
+            // A DQT segment may hold several tables: a precision/id byte,
+            // then 64 entries of 8 or 16 bits.
+            let mut offset = 0;
+            while let Some(&info) = segment.get(offset) {
+                let wide = info >> 4 == 1;
+                let size = if wide { 128 } else { 64 };
+                let entries = segment
+                    .get(offset + 1..offset + 1 + size)
+                    .ok_or("Truncated quantization table")?;
+                if info & 0x0F == 0 {
+                    let mut table = [0u16; 64];
+                    for (k, entry) in table.iter_mut().enumerate() {
+                        *entry = if wide {
+                            u16::from_be_bytes([entries[2 * k], entries[2 * k + 1]])
+                        } else {
+                            entries[k] as u16
+                        };
+                    }
+                    return Ok(table);
+                }
+                offset += 1 + size;
+            }
+        }
+        pos += 2 + len;
+    }
+
+    Err("No luminance quantization table found".to_string())
+}
@@ -0,0 +1,157 @@
+use image::{imageops, imageops::FilterType, DynamicImage, ImageBuffer, Pixel, Rgba, RgbaImage};
+
+/// Resize to exactly `width`×`height`.
+///
+/// Exact integer downscales (2×, 3×, 4×, … on both axes) of 8-bit images take
+/// a box-average path: every output pixel is the mean of its source block,
+/// which is both faster and free of Lanczos ringing. Everything else goes
+/// through `filter`.
+pub fn resize(img: &DynamicImage, width: u32, height: u32, filter: FilterType) -> DynamicImage {
+    match integer_factor(img.width(), img.height(), width, height) {
+        Some(factor) => match img {
+            DynamicImage::ImageLuma8(buf) => DynamicImage::ImageLuma8(box_downscale(buf, factor)),
+            DynamicImage::ImageLumaA8(buf) => DynamicImage::ImageLumaA8(box_downscale(buf, factor)),
+            DynamicImage::ImageRgb8(buf) => DynamicImage::ImageRgb8(box_downscale(buf, factor)),
+            DynamicImage::ImageRgba8(buf) => DynamicImage::ImageRgba8(box_downscale(buf, factor)),
+            _ => img.resize_exact(width, height, filter),
+        },
+        None => img.resize_exact(width, height, filter),
+    }
+}
+
+/// Clamp every pixel of `resized` to the per-channel min/max of the `src`
+/// pixels under its footprint, widened by one source pixel. Lanczos and
+/// Catmull-Rom overshoot past both sides of a hard edge, leaving light and
+/// dark halos; no value outside the local source range can appear after
+/// this, so halos go while the edge itself stays sharp.
+///
+/// Only 8-bit images are clamped; others are returned unchanged.
+pub fn clamp_overshoot(src: &DynamicImage, resized: &DynamicImage) -> DynamicImage {
+    match (src, resized) {
+        (DynamicImage::ImageLuma8(s), DynamicImage::ImageLuma8(r)) => {
+            DynamicImage::ImageLuma8(clamp_to_footprint(s, r))
+        }
+        (DynamicImage::ImageLumaA8(s), DynamicImage::ImageLumaA8(r)) => {
+            DynamicImage::ImageLumaA8(clamp_to_footprint(s, r))
+        }
+        (DynamicImage::ImageRgb8(s), DynamicImage::ImageRgb8(r)) => {
+            DynamicImage::ImageRgb8(clamp_to_footprint(s, r))
+        }
+        (DynamicImage::ImageRgba8(s), DynamicImage::ImageRgba8(r)) => {
+            DynamicImage::ImageRgba8(clamp_to_footprint(s, r))
+        }
+        _ => resized.clone(),
+    }
+}
+
+/// Center `img` on a `width`×`height` canvas of `background` (RGBA). An
+/// image larger than the canvas is clipped at its edges. The result keeps
+/// an alpha channel only when the image or the background has one.
+pub fn pad(img: &DynamicImage, width: u32, height: u32, background: [u8; 4]) -> DynamicImage {
+    let mut canvas = RgbaImage::from_pixel(width, height, Rgba(background));
+    let x = (width as i64 - img.width() as i64) / 2;
+    let y = (height as i64 - img.height() as i64) / 2;
+    imageops::overlay(&mut canvas, &img.to_rgba8(), x, y);
+
+    if img.color().has_alpha() || background[3] < 255 {
+        DynamicImage::ImageRgba8(canvas)
+    } else {
+        DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(canvas).to_rgb8())
+    }
+}
+
+/// Map a filter name from the options object to its `image` filter.
+pub fn parse_filter(name: &str) -> Option<FilterType> {
+    match name.to_lowercase().as_str() {
+        "nearest" => Some(FilterType::Nearest),
+        "bilinear" | "triangle" => Some(FilterType::Triangle),
+        "bicubic" | "catmullrom" | "catmull-rom" => Some(FilterType::CatmullRom),
+        "gaussian" => Some(FilterType::Gaussian),
+        "lanczos3" => Some(FilterType::Lanczos3),
+        _ => None,
+    }
+}
+
+/// The shared downscale factor when both axes shrink by the same integer.
+fn integer_factor(src_w: u32, src_h: u32, dst_w: u32, dst_h: u32) -> Option<u32> {
+    if dst_w == 0 || dst_h == 0 || src_w % dst_w != 0 || src_h % dst_h != 0 {
+        return None;
+    }
+    let factor = src_w / dst_w;
+    (factor >= 2 && src_h / dst_h == factor).then_some(factor)
+}
+
+fn box_downscale<P>(src: &ImageBuffer<P, Vec<u8>>, factor: u32) -> ImageBuffer<P, Vec<u8>>
+where
+    P: Pixel<Subpixel = u8>,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let (width, height) = (src.width() / factor, src.height() / factor);
+    let src_stride = src.width() as usize * channels;
+    // u64: a u32 sum of 255s overflows once the block passes 4104 pixels a side.
+    let area = factor as u64 * factor as u64;
+    let raw = src.as_raw();
+
+    let mut out = Vec::with_capacity(width as usize * height as usize * channels);
+    let mut sums = vec![0u64; channels];
+    for y in 0..height as usize {
+        for x in 0..width as usize {
+            sums.iter_mut().for_each(|s| *s = 0);
+            for by in 0..factor as usize {
+                let row = (y * factor as usize + by) * src_stride;
+                let start = row + x * factor as usize * channels;
+                for px in raw[start..start + factor as usize * channels].chunks_exact(channels) {
+                    for (sum, &value) in sums.iter_mut().zip(px) {
+                        *sum += value as u64;
+                    }
+                }
+            }
+            out.extend(sums.iter().map(|&sum| ((sum + area / 2) / area) as u8));
+        }
+    }
+
+    ImageBuffer::from_raw(width, height, out).expect("box downscale buffer matches its dimensions")
+}
+
+fn clamp_to_footprint<P>(
+    src: &ImageBuffer<P, Vec<u8>>,
+    resized: &ImageBuffer<P, Vec<u8>>,
+) -> ImageBuffer<P, Vec<u8>>
+where
+    P: Pixel<Subpixel = u8>,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let (src_w, src_h) = (src.width() as usize, src.height() as usize);
+    let scale_x = src_w as f64 / resized.width() as f64;
+    let scale_y = src_h as f64 / resized.height() as f64;
+    // Source range [start, end) under output index `i`, one pixel wider on
+    // each side.
+    let span = |i: u32, scale: f64, len: usize| {
+        let start = ((i as f64 * scale).floor() as usize).saturating_sub(1);
+        let end = (((i + 1) as f64 * scale).ceil() as usize + 1).min(len);
+        (start, end)
+    };
+    let raw = src.as_raw();
+
+    let mut out = resized.clone();
+    let (mut lows, mut highs) = (vec![0u8; channels], vec![0u8; channels]);
+    for (x, y, pixel) in out.enumerate_pixels_mut() {
+        let (x0, x1) = span(x, scale_x, src_w);
+        let (y0, y1) = span(y, scale_y, src_h);
+        lows.iter_mut().for_each(|v| *v = u8::MAX);
+        highs.iter_mut().for_each(|v| *v = u8::MIN);
+        for sy in y0..y1 {
+            let row = &raw[(sy * src_w + x0) * channels..(sy * src_w + x1) * channels];
+            for px in row.chunks_exact(channels) {
+                for c in 0..channels {
+                    lows[c] = lows[c].min(px[c]);
+                    highs[c] = highs[c].max(px[c]);
+                }
+            }
+        }
+        for (c, value) in pixel.channels_mut().iter_mut().enumerate() {
+            *value = (*value).clamp(lows[c], highs[c]);
+        }
+    }
+    out
+}
@@ -8,3 +8,39 @@ pub fn set_panic_hook() {
     #[cfg(feature = "console_error_panic_hook")]
     console_error_panic_hook::set_once();
 }
+
+/// Run `f`, turning a panic into an `Err` carrying its message.
+///
+/// Only effective where panics unwind. Stable wasm32 builds abort on panic,
+/// so there the instance still goes down after the panic hook has logged
+/// it; `decode::check_input` keeps malformed and oversized inputs away from
+/// the decoders for that reason. See wasm/README.md.
+pub fn catch_panic<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+        Err(format!("Panicked: {}", message))
+    })
+}
+
+/// Wall-clock deadline for `ExportOptions::time_budget_ms`. A deadline built
+/// without a budget never expires.
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline(Option<f64>);
+
+impl Deadline {
+    pub fn after_ms(budget_ms: Option<u32>) -> Deadline {
+        Deadline(budget_ms.map(|ms| js_sys::Date::now() + ms as f64))
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.0.is_some()
+    }
+
+    pub fn expired(&self) -> bool {
+        self.0.is_some_and(|end| js_sys::Date::now() > end)
+    }
+}
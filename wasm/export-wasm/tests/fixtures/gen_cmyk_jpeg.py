@@ -0,0 +1,45 @@
+# Writes an 8x8 solid-color, 4-component baseline JPEG for the decode tests.
+#   python3 gen_cmyk_jpeg.py OUT C,M,Y,K|Y,Cb,Cr,K TRANSFORM
+# TRANSFORM is the Adobe APP14 transform (0 CMYK, 2 YCCK) or "none" to omit
+# the marker. Samples are stored as given, so pass Adobe-inverted CMYK.
+#   adobe_cmyk.jpg: 204,102,255,230 0
+#   adobe_ycck.jpg: 105,69,89,230 2   (the same color as YCCK)
+import struct, sys
+# Standard luminance DC / AC tables (Annex K)
+dc_bits=[0,1,5,1,1,1,1,1,1,0,0,0,0,0,0,0]; dc_vals=list(range(12))
+ac_bits=[0,2,1,3,3,2,4,3,5,5,4,4,0,0,1,0x7d]
+ac_vals=bytes.fromhex("01020300041105122131410613516107227114328191a1082342b1c11552d1f02433627282090a161718191a25262728292a3435363738393a434445464748494a535455565758595a636465666768696a737475767778797a838485868788898a92939495969798999aa2a3a4a5a6a7a8a9aab2b3b4b5b6b7b8b9bac2c3c4c5c6c7c8c9cad2d3d4d5d6d7d8d9dae1e2e3e4e5e6e7e8e9eaf1f2f3f4f5f6f7f8f9fa")
+def codes(bits, vals):
+    c={}; code=0; k=0
+    for l in range(1,17):
+        for _ in range(bits[l-1]):
+            c[vals[k]]=(code,l); code+=1; k+=1
+        code<<=1
+    return c
+dc=codes(dc_bits,dc_vals); ac=codes(ac_bits,list(ac_vals))
+def seg(m,p): return bytes([0xFF,m])+struct.pack(">H",len(p)+2)+p
+def make(values, transform):
+    n=len(values); out=b"\xff\xd8"
+    if transform is not None:
+        out+=seg(0xEE,b"Adobe"+bytes([0,100,0,0,0,0,transform]))
+    out+=seg(0xDB,bytes([0])+bytes([1]*64))
+    out+=seg(0xC0,bytes([8])+struct.pack(">HH",8,8)+bytes([n])+b"".join(bytes([i+1,0x11,0]) for i in range(n)))
+    out+=seg(0xC4,bytes([0x00])+bytes(dc_bits)+bytes(dc_vals))
+    out+=seg(0xC4,bytes([0x10])+bytes(ac_bits)+ac_vals)
+    out+=seg(0xDA,bytes([n])+b"".join(bytes([i+1,0x00]) for i in range(n))+bytes([0,63,0]))
+    bits=""
+    for v in values:
+        d=8*(v-128)
+        cat=0 if d==0 else abs(d).bit_length()
+        c,l=dc[cat]; bits+=format(c,"0%db"%l)
+        if cat: bits+=format(d if d>0 else d+(1<<cat)-1,"0%db"%cat)
+        c,l=ac[0]; bits+=format(c,"0%db"%l)
+    bits+="1"*((-len(bits))%8)
+    data=bytearray()
+    for i in range(0,len(bits),8):
+        b=int(bits[i:i+8],2); data.append(b)
+        if b==0xFF: data.append(0)
+    return out+bytes(data)+b"\xff\xd9"
+vals=[int(x) for x in sys.argv[2].split(",")]
+t=None if sys.argv[3]=="none" else int(sys.argv[3])
+open(sys.argv[1],"wb").write(make(vals,t))
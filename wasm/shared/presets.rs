@@ -0,0 +1,16 @@
+//! `quality_preset` names shared by export-wasm (`quality::preset`) and
+//! thumbnail-wasm (`preset_quality`). Both crates include this file through
+//! `#[path]`, so the JPEG quality behind a preset name cannot drift between
+//! full exports and thumbnails.
+
+/// JPEG quality (1-100) of each preset name.
+pub const JPEG_QUALITIES: [(&str, u8); 4] =
+    [("low", 60), ("medium", 75), ("high", 90), ("max", 100)];
+
+/// JPEG quality of preset `name`, matched case-insensitively.
+pub fn jpeg_quality(name: &str) -> Option<u8> {
+    JPEG_QUALITIES
+        .iter()
+        .find(|(preset, _)| preset.eq_ignore_ascii_case(name))
+        .map(|&(_, quality)| quality)
+}
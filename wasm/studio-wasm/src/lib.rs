@@ -5,6 +5,10 @@ const LUMA_R: f32 = 0.2126;
 const LUMA_G: f32 = 0.7152;
 const LUMA_B: f32 = 0.0722;
 
+/// Share of contrast, saturation and vibrance withheld from pixels fully
+/// inside the skin-tone mask when `skin_tone_protect` is on.
+const SKIN_PROTECT_STRENGTH: f32 = 0.75;
+
 #[derive(Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct StudioAdjustments {
@@ -21,12 +25,14 @@ pub struct StudioAdjustments {
     clarity: f32,
     sharpness: f32,
     noise_reduction: f32,
+    skin_tone_protect: Option<bool>,
 }
 
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ProcessResult {
     success: bool,
+    #[serde(with = "serde_bytes")]
     data: Vec<u8>,
     width: u32,
     height: u32,
@@ -104,6 +110,14 @@ fn process_pixels(
                 srgb_to_linear(rgba[idx + 2] as f32 / 255.0),
             ];
 
+            // Adjustments a pixel in the skin-tone band only partly receives.
+            let protect = if adjustments.skin_tone_protect.unwrap_or(false) {
+                1.0 - skin_tone_mask([rgba[idx], rgba[idx + 1], rgba[idx + 2]])
+                    * SKIN_PROTECT_STRENGTH
+            } else {
+                1.0
+            };
+
             let blurred = if needs_blur {
                 neighbor_average_linear(rgba, width, height, x, y)
             } else {
@@ -117,7 +131,7 @@ fn process_pixels(
             color = apply_tonal_adjustments(
                 color,
                 blurred,
-                adjustments.contrast / 100.0,
+                adjustments.contrast / 100.0 * protect,
                 adjustments.shadows / 100.0,
                 adjustments.whites / 100.0,
                 adjustments.blacks / 100.0,
@@ -129,8 +143,8 @@ fn process_pixels(
             );
             color = apply_creative_color(
                 color,
-                adjustments.saturation / 100.0,
-                adjustments.vibrance / 100.0,
+                adjustments.saturation / 100.0 * protect,
+                adjustments.vibrance / 100.0 * protect,
             );
             color = apply_clarity(color, adjustments.clarity / 100.0);
 
@@ -174,6 +188,35 @@ impl StudioAdjustments {
     }
 }
 
+/// 0–1 weight of how skin-like an sRGB color is: an HSV hue between red and
+/// orange (about 0–50°), moderate saturation and not too dark. Edges are
+/// feathered so the protection fades out instead of banding.
+fn skin_tone_mask(rgb: [u8; 3]) -> f32 {
+    let [r, g, b] = rgb.map(|v| v as f32 / 255.0);
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+    if max <= 0.0 || delta <= 0.0 {
+        return 0.0;
+    }
+
+    let hue = if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    // Distance from the band 0–50°, wrapping so magenta-reds near 360° count.
+    let hue_distance = if hue > 180.0 { 360.0 - hue } else { (hue - 50.0).max(0.0) };
+    let saturation = delta / max;
+
+    (1.0 - smoothstep(0.0, 15.0, hue_distance))
+        * smoothstep(0.1, 0.2, saturation)
+        * (1.0 - smoothstep(0.6, 0.8, saturation))
+        * smoothstep(0.15, 0.3, max)
+}
+
 fn sample_linear(rgba: &[u8], width: u32, height: u32, x: i32, y: i32) -> [f32; 3] {
     let xx = x.clamp(0, width as i32 - 1) as u32;
     let yy = y.clamp(0, height as i32 - 1) as u32;
@@ -0,0 +1,205 @@
+use image::{DynamicImage, ImageReader};
+use std::io::Cursor;
+use wasm_bindgen::prelude::*;
+
+pub fn decode(buffer: &[u8]) -> Result<DynamicImage, JsError> {
+    decode_image(buffer).map_err(|e| JsError::new(&e))
+}
+
+/// `decode` with a plain error. Inputs failing `check_input` are rejected
+/// before any decoder runs.
+pub fn decode_image(buffer: &[u8]) -> Result<DynamicImage, String> {
+    check_input(buffer)?;
+    #[cfg(test)]
+    tests::maybe_panic();
+
+    if is_heif(buffer) {
+        return decode_heif(buffer);
+    }
+
+    #[cfg(feature = "jpeg-decoder")]
+    if image::guess_format(buffer).ok() == Some(image::ImageFormat::Jpeg) {
+        return decode_jpeg(buffer).map_err(|e| format!("Decode error: {}", e));
+    }
+
+    ImageReader::new(Cursor::new(buffer))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .decode()
+        .map_err(|e| format!("Decode error: {}", e))
+}
+
+/// Largest encoded input accepted, in bytes.
+pub const MAX_INPUT_BYTES: usize = 256 * 1024 * 1024;
+/// Largest source accepted, in pixels: 128 MP, whose RGBA8 buffer is
+/// `image`'s default 512 MiB allocation limit.
+pub const MAX_INPUT_PIXELS: u64 = 128 * 1024 * 1024;
+
+/// Screen `buffer` from its header alone, the same checks as export-wasm:
+/// within `MAX_INPUT_BYTES`, a parseable header, and a non-empty frame
+/// within `MAX_INPUT_PIXELS`. wasm32 aborts on panic, so this is what keeps
+/// truncated or oversized uploads away from the decoders. HEIF headers are
+/// left to libheif.
+pub fn check_input(buffer: &[u8]) -> Result<(), String> {
+    if buffer.len() > MAX_INPUT_BYTES {
+        return Err(format!(
+            "Input is {} bytes, more than the {} byte limit",
+            buffer.len(),
+            MAX_INPUT_BYTES
+        ));
+    }
+    if is_heif(buffer) {
+        return Ok(());
+    }
+
+    let (width, height) = ImageReader::new(Cursor::new(buffer))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?
+        .into_dimensions()
+        .map_err(|e| format!("Unreadable image header: {}", e))?;
+    check_dimensions(width, height)
+}
+
+pub fn check_dimensions(width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err("Image has zero width or height".to_string());
+    }
+    if width as u64 * height as u64 > MAX_INPUT_PIXELS {
+        return Err(format!(
+            "Image is {}x{}, more than the {} pixel limit",
+            width, height, MAX_INPUT_PIXELS
+        ));
+    }
+    Ok(())
+}
+
+/// The JPEG preview in the EXIF thumbnail IFD (IFD1) of `buffer`, if there
+/// is one. Only the EXIF block is parsed; the main image is not decoded.
+pub fn embedded_thumbnail(buffer: &[u8]) -> Option<Vec<u8>> {
+    use exif::{In, Reader, Tag};
+
+    let exif = Reader::new().read_from_container(&mut Cursor::new(buffer)).ok()?;
+    let field_u32 = |tag| exif.get_field(tag, In::THUMBNAIL)?.value.get_uint(0);
+    // The offset is relative to the TIFF header, which is where `buf` starts.
+    let offset = field_u32(Tag::JPEGInterchangeFormat)? as usize;
+    let length = field_u32(Tag::JPEGInterchangeFormatLength)? as usize;
+    exif.buf().get(offset..offset.checked_add(length)?).map(<[u8]>::to_vec)
+}
+
+/// ISO-BMFF `ftyp` brands written by HEIF/HEIC encoders.
+const HEIF_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis", b"mif1", b"msf1",
+];
+
+pub fn is_heif(bytes: &[u8]) -> bool {
+    bytes.len() >= 12 && &bytes[4..8] == b"ftyp" && HEIF_BRANDS.iter().any(|b| &bytes[8..12] == *b)
+}
+
+/// Decode the primary HEIF image through libheif, which also applies the
+/// container's rotation, mirroring and crop.
+#[cfg(feature = "heif")]
+fn decode_heif(bytes: &[u8]) -> Result<DynamicImage, String> {
+    use image::RgbaImage;
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let context = HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = context.primary_image_handle().map_err(|e| e.to_string())?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        .map_err(|e| e.to_string())?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or("HEIF image has no interleaved RGBA plane")?;
+
+    let row_len = plane.width as usize * 4;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_len]);
+    }
+
+    RgbaImage::from_raw(plane.width, plane.height, pixels)
+        .map(DynamicImage::ImageRgba8)
+        .ok_or_else(|| "HEIF buffer does not match its dimensions".to_string())
+}
+
+/// Width and height of the primary HEIF image, read from the container
+/// without decoding it.
+#[cfg(feature = "heif")]
+pub fn heif_dimensions(bytes: &[u8]) -> Result<(u32, u32), String> {
+    use libheif_rs::HeifContext;
+
+    let context = HeifContext::read_from_bytes(bytes).map_err(|e| e.to_string())?;
+    let handle = context.primary_image_handle().map_err(|e| e.to_string())?;
+    Ok((handle.width(), handle.height()))
+}
+
+#[cfg(not(feature = "heif"))]
+pub fn heif_dimensions(_bytes: &[u8]) -> Result<(u32, u32), String> {
+    Err("HEIF/HEIC decoding requires the `heif` feature".to_string())
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(_bytes: &[u8]) -> Result<DynamicImage, String> {
+    Err("Unsupported format: HEIF/HEIC decoding requires the `heif` feature".to_string())
+}
+
+/// Decode JPEG through `jpeg-decoder` rather than `image`'s zune-jpeg backend.
+#[cfg(feature = "jpeg-decoder")]
+fn decode_jpeg(buffer: &[u8]) -> Result<DynamicImage, String> {
+    use image::{GrayImage, ImageBuffer, Luma, RgbImage};
+    use jpeg_decoder::{Decoder, PixelFormat};
+
+    let mut decoder = Decoder::new(Cursor::new(buffer));
+    let pixels = decoder.decode().map_err(|e| e.to_string())?;
+    let info = decoder.info().ok_or("Missing JPEG frame info")?;
+    let (width, height) = (info.width as u32, info.height as u32);
+
+    let img = match info.pixel_format {
+        PixelFormat::L8 => GrayImage::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        PixelFormat::L16 => {
+            let samples = pixels
+                .chunks_exact(2)
+                .map(|b| u16::from_ne_bytes([b[0], b[1]]))
+                .collect();
+            ImageBuffer::<Luma<u16>, _>::from_raw(width, height, samples).map(DynamicImage::ImageLuma16)
+        }
+        PixelFormat::RGB24 => RgbImage::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        PixelFormat::CMYK32 => {
+            // `jpeg-decoder` has already undone the Adobe APP14 transform
+            // (YCCK) and inversion, so these are plain CMYK. Same formula as
+            // zune-jpeg, see wasm/README.md.
+            let rgb = pixels
+                .chunks_exact(4)
+                .flat_map(|p| {
+                    let k = 255 - p[3] as u16;
+                    [0, 1, 2].map(|i| ((255 - p[i] as u16) * k / 255) as u8)
+                })
+                .collect();
+            RgbImage::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8)
+        }
+    };
+
+    img.ok_or_else(|| "JPEG buffer does not match its dimensions".to_string())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::Cell;
+
+    thread_local! {
+        static PANIC_ON_DECODE: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Make the next decode on this thread panic after `check_input` has
+    /// passed, standing in for a decoder bug on a well-formed header.
+    pub(crate) fn panic_on_next_decode() {
+        PANIC_ON_DECODE.with(|flag| flag.set(true));
+    }
+
+    pub(super) fn maybe_panic() {
+        if PANIC_ON_DECODE.with(|flag| flag.replace(false)) {
+            panic!("crafted decoder panic");
+        }
+    }
+}
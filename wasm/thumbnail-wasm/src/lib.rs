@@ -1,12 +1,110 @@
+mod decode;
+mod ocr;
+mod phash;
+#[path = "../../shared/presets.rs"]
+mod presets;
+mod saliency;
+
 use wasm_bindgen::prelude::*;
-use image::{ImageFormat, ImageReader};
+use image::{
+    codecs::jpeg::JpegEncoder, codecs::png::PngEncoder, imageops, metadata::Orientation, DynamicImage, ExtendedColorType,
+    ImageEncoder, ImageReader, RgbaImage,
+};
+use serde::{Deserialize, Serialize};
 use std::io::Cursor;
 
+#[derive(Deserialize, Debug, Default)]
+pub struct ThumbnailOptions {
+    pub linear: Option<bool>, // downscale in linear light; slower, keeps brightness
+    pub grayscale: Option<bool>, // single-channel JPEG; defaults to true for luma sources
+    pub cover: Option<bool>,     // crop to a max_size square instead of fitting inside it
+    pub focal_x: Option<f32>,    // 0.0-1.0 horizontal crop center for `cover`, default 0.5
+    pub focal_y: Option<f32>,    // 0.0-1.0 vertical crop center for `cover`, default 0.5
+    pub orientation_override: Option<u8>, // EXIF orientation 1-8, same transforms as export-wasm
+    pub quality_preset: Option<String>, // JPEG quality: "low" 60, "medium" 75, "high" 90, "max" 100
+    pub background_rgb: Option<[u8; 3]>, // fill behind transparent areas, default white
+    pub max_bytes: Option<u32>, // highest quality (up to the preset) that fits, shrinking if needed
+}
+
+/// JPEG quality used without a preset, the encoder's own default.
+const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+/// Lowest quality `max_bytes` searches down to. Below it blocking dominates,
+/// and a smaller thumbnail at this quality looks better.
+const MIN_BUDGET_QUALITY: u8 = 30;
+
+/// Factor each side shrinks by when `max_bytes` is out of reach at
+/// `MIN_BUDGET_QUALITY`.
+const BUDGET_SHRINK: f32 = 0.75;
+
+/// JPEG quality of a `quality_preset`, shared with export-wasm's
+/// `quality::preset` through `wasm/shared/presets.rs`.
+fn preset_quality(name: &str) -> Option<u8> {
+    presets::jpeg_quality(name)
+}
+
+#[derive(Serialize)]
+struct Size {
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct AspectRatio {
+    width: u32,
+    height: u32,
+    ratio_w: u32,
+    ratio_h: u32,
+    ratio_float: f64,
+}
+
+#[derive(Serialize)]
+struct CropRect {
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct CropSuggestion {
+    aspect: String,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+}
+
+#[derive(Serialize)]
+struct NearDuplicateGroups {
+    clusters: Vec<Vec<usize>>,
+    failed: Vec<usize>,
+}
+
+#[derive(Serialize)]
+struct EmbeddedThumbnail {
+    present: bool,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct ProbeResult {
+    decodable: bool,
+    format: Option<String>,
+    width: u32,
+    height: u32,
+    error: Option<String>,
+}
+
 #[wasm_bindgen]
 pub struct ThumbnailResult {
     width: u32,
     height: u32,
     data: Vec<u8>,
+    input_bytes: u32,
+    output_bytes: u32,
+    quality: u8,
 }
 
 #[wasm_bindgen]
@@ -19,30 +117,577 @@ impl ThumbnailResult {
 
     #[wasm_bindgen(getter)]
     pub fn data(&self) -> Vec<u8> { self.data.clone() }
+
+    #[wasm_bindgen(getter)]
+    pub fn input_bytes(&self) -> u32 { self.input_bytes }
+
+    #[wasm_bindgen(getter)]
+    pub fn output_bytes(&self) -> u32 { self.output_bytes }
+
+    /// JPEG quality the data was encoded with.
+    #[wasm_bindgen(getter)]
+    pub fn quality(&self) -> u8 { self.quality }
 }
 
 
 #[wasm_bindgen]
 pub fn generate_thumbnail(buffer: &[u8], max_size: u32) -> Result<Vec<u8>, JsError> {
-    let img = match ImageReader::new(Cursor::new(buffer))
+    thumbnail(buffer, max_size, &ThumbnailOptions::default()).map(|r| r.data)
+}
+
+/// `generate_thumbnail` with a `ThumbnailOptions` object; omitted fields keep
+/// the defaults of the plain call.
+#[wasm_bindgen]
+pub fn generate_thumbnail_with_options(
+    buffer: &[u8],
+    max_size: u32,
+    options_js: JsValue,
+) -> Result<Vec<u8>, JsError> {
+    let options: ThumbnailOptions = serde_wasm_bindgen::from_value(options_js)
+        .map_err(|e| JsError::new(&format!("Invalid options: {}", e)))?;
+    thumbnail(buffer, max_size, &options).map(|r| r.data)
+}
+
+/// `generate_thumbnail_with_options` returning a `ThumbnailResult`, which adds
+/// the output dimensions and the input/output byte sizes for measuring
+/// compression across a library.
+#[wasm_bindgen]
+pub fn generate_thumbnail_result(
+    buffer: &[u8],
+    max_size: u32,
+    options_js: JsValue,
+) -> Result<ThumbnailResult, JsError> {
+    let options: ThumbnailOptions = serde_wasm_bindgen::from_value(options_js)
+        .map_err(|e| JsError::new(&format!("Invalid options: {}", e)))?;
+    thumbnail(buffer, max_size, &options)
+}
+
+/// Black-and-white PNG thumbnail prepared for text recognition: grayscale,
+/// levels stretched to the 1st–99th percentile, then an adaptive threshold
+/// (see `ocr::adaptive_threshold`) so text stays legible under shadows and
+/// uneven lighting. Sized like `generate_thumbnail`.
+#[wasm_bindgen]
+pub fn generate_ocr_thumbnail(buffer: &[u8], max_size: u32) -> Result<Vec<u8>, JsError> {
+    let img = decode::decode(buffer)?;
+    let (width, height) = calculate_size(img.width(), img.height(), max_size);
+    let mut gray = imageops::resize(
+        &img.into_luma8(),
+        width.max(1),
+        height.max(1),
+        imageops::FilterType::Triangle,
+    );
+    ocr::auto_contrast(&mut gray);
+    let binary = ocr::adaptive_threshold(&gray);
+
+    let mut data = Vec::new();
+    PngEncoder::new(&mut data)
+        .write_image(binary.as_raw(), binary.width(), binary.height(), ExtendedColorType::L8)
+        .map_err(|e| JsError::new(&format!("Encode error: {}", e)))?;
+    Ok(data)
+}
+
+/// Dimensions `generate_thumbnail` will produce for `buffer`, read from the
+/// image header without decoding pixels. Lets a grid reserve space before
+/// the thumbnail exists.
+#[wasm_bindgen]
+pub fn placeholder_size(buffer: &[u8], max_size: u32) -> Result<JsValue, JsError> {
+    let (orig_w, orig_h) = ImageReader::new(Cursor::new(buffer))
         .with_guessed_format()?
-        .decode()
-    {
-        Ok(img) => img,
-        Err(e) => return Err(JsError::new(&format!("Decode error: {}", e)))
+        .into_dimensions()
+        .map_err(|e| JsError::new(&format!("Decode error: {}", e)))?;
+
+    let (width, height) = calculate_size(orig_w, orig_h, max_size);
+    serde_wasm_bindgen::to_value(&Size { width, height }).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Thumbnail `max_size` for a `container_px` slot on a screen with
+/// `device_pixel_ratio`: `container_px * dpr` rounded up so the slot never
+/// shows an upscaled image, capped at `max_cap`. A DPR that is not a
+/// positive number counts as 1. Never returns 0.
+#[wasm_bindgen]
+pub fn recommended_thumb_size(container_px: u32, device_pixel_ratio: f32, max_cap: u32) -> u32 {
+    let dpr = if device_pixel_ratio.is_finite() && device_pixel_ratio > 0.0 {
+        device_pixel_ratio as f64
+    } else {
+        1.0
     };
+    // 1.1 as f32 is a hair above 1.1; without the slack 300 × 1.1 would
+    // round up to 331.
+    let wanted = (container_px as f64 * dpr - 0.01).ceil().min(u32::MAX as f64) as u32;
+    wanted.min(max_cap).max(1)
+}
 
-    let (width, height) = calculate_size(img.width(), img.height(), max_size);
-    let thumbnail = img.thumbnail(width, height);
+/// `{ width, height, ratio_w, ratio_h, ratio_float }` for `buffer`, with
+/// the ratio reduced by the GCD (6000×4000 gives 3:2) and `ratio_float`
+/// as width / height. Only the header is read. Exact reduction means
+/// near-standard sizes such as 1366×768 report 683:384, not 16:9.
+#[wasm_bindgen]
+pub fn aspect_ratio(buffer: &[u8]) -> Result<JsValue, JsError> {
+    let (width, height) = ImageReader::new(Cursor::new(buffer))
+        .with_guessed_format()?
+        .into_dimensions()
+        .map_err(|e| JsError::new(&format!("Decode error: {}", e)))?;
+    if width == 0 || height == 0 {
+        return Err(JsError::new("Image has zero width or height"));
+    }
 
-    // Convert to RGB8 which is supported by JPEG encoder
-    let rgb_image = thumbnail.into_rgb8();
+    let divisor = gcd(width, height);
+    let ratio = AspectRatio {
+        width,
+        height,
+        ratio_w: width / divisor,
+        ratio_h: height / divisor,
+        ratio_float: width as f64 / height as f64,
+    };
+    serde_wasm_bindgen::to_value(&ratio).map_err(|e| JsError::new(&e.to_string()))
+}
 
-    let mut output = Cursor::new(Vec::new());
-    rgb_image.write_to(&mut output, ImageFormat::Jpeg)
-        .map_err(|e| JsError::new(&format!("Encode error: {}", e)))?;
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
 
-    Ok(output.into_inner())
+/// Source rectangle `{ x, y, w, h }` that a cover crop of a `width`×`height`
+/// image to `target_w`×`target_h` keeps, centered on the focal point
+/// (0.0–1.0 on each axis) as far as the image bounds allow. The `cover`
+/// thumbnail option crops with this same rectangle, so UI overlays match.
+#[wasm_bindgen]
+pub fn compute_cover_crop(
+    width: u32,
+    height: u32,
+    target_w: u32,
+    target_h: u32,
+    focal_x: f32,
+    focal_y: f32,
+) -> Result<JsValue, JsError> {
+    if width == 0 || height == 0 || target_w == 0 || target_h == 0 {
+        return Err(JsError::new("Dimensions must be non-zero"));
+    }
+    let rect = cover_crop(width, height, target_w, target_h, focal_x, focal_y);
+    serde_wasm_bindgen::to_value(&rect).map_err(|e| JsError::new(&e.to_string()))
+}
+
+fn cover_crop(
+    width: u32,
+    height: u32,
+    target_w: u32,
+    target_h: u32,
+    focal_x: f32,
+    focal_y: f32,
+) -> CropRect {
+    let scale = (target_w as f32 / width as f32).max(target_h as f32 / height as f32);
+    let w = ((target_w as f32 / scale).round() as u32).clamp(1, width);
+    let h = ((target_h as f32 / scale).round() as u32).clamp(1, height);
+
+    let center_x = focal_x.clamp(0.0, 1.0) * width as f32;
+    let center_y = focal_y.clamp(0.0, 1.0) * height as f32;
+    let x = (center_x - w as f32 / 2.0).round().clamp(0.0, (width - w) as f32) as u32;
+    let y = (center_y - h as f32 / 2.0).round().clamp(0.0, (height - h) as f32) as u32;
+
+    CropRect { x, y, w, h }
+}
+
+/// Suggest a crop for each aspect ratio in `aspects_js` (strings like
+/// `"16:9"`), returning `[{ aspect, x, y, w, h }]` in full-resolution
+/// pixels. Each crop is the largest of its aspect that fits, placed where
+/// edge density is highest on a 128px copy, with a bias toward the center.
+/// A heuristic, not subject detection: busy backgrounds can win over a
+/// smooth subject.
+#[wasm_bindgen]
+pub fn suggest_crops(buffer: &[u8], aspects_js: JsValue) -> Result<JsValue, JsError> {
+    let aspects: Vec<String> = serde_wasm_bindgen::from_value(aspects_js)
+        .map_err(|e| JsError::new(&format!("Invalid aspects: {}", e)))?;
+    let img = decode::decode(buffer)?;
+    let map = saliency::SaliencyMap::new(&img);
+
+    let mut suggestions = Vec::with_capacity(aspects.len());
+    for aspect in aspects {
+        let (aspect_w, aspect_h) = parse_aspect(&aspect)
+            .ok_or_else(|| JsError::new(&format!("Invalid aspect ratio: {}", aspect)))?;
+
+        // Pick the window on the small map, then rebuild the same crop at
+        // full resolution around its center.
+        let window = cover_crop(map.width, map.height, aspect_w, aspect_h, 0.5, 0.5);
+        let (x, y) = map.best_window(window.w, window.h);
+        let focal_x = (x as f32 + window.w as f32 / 2.0) / map.width as f32;
+        let focal_y = (y as f32 + window.h as f32 / 2.0) / map.height as f32;
+        let rect = cover_crop(img.width(), img.height(), aspect_w, aspect_h, focal_x, focal_y);
+
+        suggestions.push(CropSuggestion {
+            aspect,
+            x: rect.x,
+            y: rect.y,
+            w: rect.w,
+            h: rect.h,
+        });
+    }
+
+    serde_wasm_bindgen::to_value(&suggestions).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// `"w:h"` as integer proportions `cover_crop` accepts; decimal parts such
+/// as `"1.91:1"` are kept to three places.
+fn parse_aspect(aspect: &str) -> Option<(u32, u32)> {
+    let (w, h) = aspect.split_once(':')?;
+    let scaled = |v: &str| -> Option<u32> {
+        let v: f32 = v.trim().parse().ok()?;
+        let scaled = (v * 1000.0).round();
+        if scaled >= 1.0 && scaled <= u32::MAX as f32 {
+            Some(scaled as u32)
+        } else {
+            None
+        }
+    };
+    Some((scaled(w)?, scaled(h)?))
+}
+
+/// 64-bit DCT perceptual hash of `buffer` as 16 hex digits. See
+/// `phash_normalization` for the fixed preprocessing applied first.
+#[wasm_bindgen]
+pub fn perceptual_hash(buffer: &[u8]) -> Result<String, JsError> {
+    let img = decode::decode(buffer)?;
+    Ok(format!("{:016x}", phash::phash(&img)))
+}
+
+/// Group the images in `buffers_js` (an array of `Uint8Array`) whose
+/// perceptual hashes differ in at most `max_distance` of 64 bits. Returns
+/// `{ clusters, failed }`: index lists of two or more near-duplicates, and
+/// the indices that could not be decoded.
+///
+/// Grouping is transitive (A~B and B~C put A, B, C together) and compares
+/// every pair, so cost grows quadratically; batches of a few thousand are
+/// fine, larger libraries should be bucketed first. A distance of about 8–10
+/// catches re-encodes and resizes without merging merely similar scenes.
+#[wasm_bindgen]
+pub fn group_near_duplicates(buffers_js: js_sys::Array, max_distance: u32) -> Result<JsValue, JsError> {
+    let mut hashes = Vec::new();
+    let mut failed = Vec::new();
+    for (index, buffer) in buffers_js.iter().enumerate() {
+        let bytes = js_sys::Uint8Array::new(&buffer).to_vec();
+        match hash_item(&bytes) {
+            Some(hash) => hashes.push((index, hash)),
+            None => failed.push(index),
+        }
+    }
+
+    let groups = NearDuplicateGroups {
+        clusters: phash::group(&hashes, max_distance),
+        failed,
+    };
+    serde_wasm_bindgen::to_value(&groups).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// pHash of one `group_near_duplicates` input, `None` when it fails
+/// `decode::check_input` or does not decode. A decoder panic fails only this
+/// item where panics unwind; see wasm/README.md.
+fn hash_item(bytes: &[u8]) -> Option<u64> {
+    std::panic::catch_unwind(|| decode::decode_image(bytes).map(|img| phash::phash(&img)))
+        .ok()?
+        .ok()
+}
+
+/// Parameters images are normalized with before `perceptual_hash`:
+/// `{ size, hash_size, filter, color }`.
+#[wasm_bindgen]
+pub fn phash_normalization() -> Result<JsValue, JsError> {
+    serde_wasm_bindgen::to_value(&phash::normalization()).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Cheap upload validation: `true` when the header of `buffer` parses with a
+/// decoder this build supports. Pixels are not decoded.
+#[wasm_bindgen]
+pub fn is_decodable(buffer: &[u8]) -> bool {
+    probe(buffer).decodable
+}
+
+/// Detailed form of `is_decodable` returning
+/// `{ decodable, format, width, height, error }`.
+#[wasm_bindgen]
+pub fn probe_decodable(buffer: &[u8]) -> Result<JsValue, JsError> {
+    serde_wasm_bindgen::to_value(&probe(buffer)).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// `{ present, width, height }` of the EXIF preview JPEG embedded in
+/// `buffer`, read from its EXIF block and the preview's own header without
+/// decoding either image. Width and height are 0 when no usable preview is
+/// present, so callers can compare them against the size they need and fall
+/// back to `generate_thumbnail` when it is too small.
+#[wasm_bindgen]
+pub fn probe_embedded_thumbnail(buffer: &[u8]) -> Result<JsValue, JsError> {
+    let size = decode::embedded_thumbnail(buffer).and_then(|preview| {
+        ImageReader::with_format(Cursor::new(preview), image::ImageFormat::Jpeg)
+            .into_dimensions()
+            .ok()
+    });
+    let probe = match size {
+        Some((width, height)) => EmbeddedThumbnail { present: true, width, height },
+        None => EmbeddedThumbnail { present: false, width: 0, height: 0 },
+    };
+    serde_wasm_bindgen::to_value(&probe).map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Edge length of the downscaled copy `is_blank` measures.
+const BLANK_PROBE_SIZE: u32 = 64;
+
+/// `true` when `buffer` is near one solid color: the standard deviation of
+/// every RGB channel, measured on a copy downscaled to fit 64×64, is at most
+/// `tolerance` (in 0–255 levels). Downscaling averages away most sensor
+/// and scanner noise, so a tolerance of a few levels is usually enough.
+#[wasm_bindgen]
+pub fn is_blank(buffer: &[u8], tolerance: u8) -> Result<bool, JsError> {
+    let probe = decode::decode(buffer)?
+        .thumbnail(BLANK_PROBE_SIZE, BLANK_PROBE_SIZE)
+        .to_rgb8();
+    let count = probe.pixels().len() as f64;
+
+    let mut sum = [0.0f64; 3];
+    let mut sum_sq = [0.0f64; 3];
+    for pixel in probe.pixels() {
+        for c in 0..3 {
+            let v = pixel[c] as f64;
+            sum[c] += v;
+            sum_sq[c] += v * v;
+        }
+    }
+
+    Ok((0..3).all(|c| {
+        let mean = sum[c] / count;
+        let variance = (sum_sq[c] / count - mean * mean).max(0.0);
+        variance.sqrt() <= tolerance as f64
+    }))
+}
+
+/// Parse the header with the matching decoder (libheif for HEIF) and apply
+/// the limits of `decode::check_input`. Only the header is read, so a file
+/// whose pixel data is corrupt still reports `decodable`. Panics inside a
+/// decoder are reported as undecodable, though on wasm32 (panic = abort)
+/// they still abort the instance.
+fn probe(buffer: &[u8]) -> ProbeResult {
+    let failure = |format: Option<String>, error: String| ProbeResult {
+        decodable: false,
+        format,
+        width: 0,
+        height: 0,
+        error: Some(error),
+    };
+
+    if buffer.len() > decode::MAX_INPUT_BYTES {
+        return failure(
+            None,
+            format!("Input is {} bytes, more than the {} byte limit", buffer.len(), decode::MAX_INPUT_BYTES),
+        );
+    }
+    let (format_name, header) = if decode::is_heif(buffer) {
+        ("heif".to_string(), std::panic::catch_unwind(|| decode::heif_dimensions(buffer)))
+    } else {
+        let format = match image::guess_format(buffer) {
+            Ok(format) => format,
+            Err(e) => return failure(None, e.to_string()),
+        };
+        let header = std::panic::catch_unwind(|| {
+            ImageReader::with_format(Cursor::new(buffer), format)
+                .into_dimensions()
+                .map_err(|e| e.to_string())
+        });
+        (format!("{:?}", format).to_lowercase(), header)
+    };
+    match header {
+        Ok(Ok((width, height))) => match decode::check_dimensions(width, height) {
+            Ok(()) => ProbeResult {
+                decodable: true,
+                format: Some(format_name),
+                width,
+                height,
+                error: None,
+            },
+            Err(e) => failure(Some(format_name), e),
+        },
+        Ok(Err(e)) => failure(Some(format_name), e),
+        Err(_) => failure(Some(format_name), "Decoder panicked while reading the header".to_string()),
+    }
+}
+
+fn thumbnail(
+    buffer: &[u8],
+    max_size: u32,
+    options: &ThumbnailOptions,
+) -> Result<ThumbnailResult, JsError> {
+    let quality = match options.quality_preset.as_deref() {
+        Some(name) => preset_quality(name)
+            .ok_or_else(|| JsError::new(&format!("Unknown quality preset: {}", name)))?,
+        None => DEFAULT_JPEG_QUALITY,
+    };
+
+    let mut img = decode::decode(buffer)?;
+    if let Some(value) = options.orientation_override {
+        let orientation = Orientation::from_exif(value).ok_or_else(|| {
+            JsError::new(&format!("Invalid orientation: {} (expected 1-8)", value))
+        })?;
+        img.apply_orientation(orientation);
+    }
+    let grayscale = options
+        .grayscale
+        .unwrap_or_else(|| !img.color().has_color());
+
+    let (img, (width, height)) = if options.cover.unwrap_or(false) {
+        let rect = cover_crop(
+            img.width(),
+            img.height(),
+            max_size,
+            max_size,
+            options.focal_x.unwrap_or(0.5),
+            options.focal_y.unwrap_or(0.5),
+        );
+        (img.crop_imm(rect.x, rect.y, rect.w, rect.h), (max_size, max_size))
+    } else {
+        let size = calculate_size(img.width(), img.height(), max_size);
+        (img, size)
+    };
+
+    let thumbnail = if options.linear.unwrap_or(false) {
+        thumbnail_linear(&img, width, height)
+    } else {
+        img.thumbnail_exact(width, height)
+    };
+
+    let mut thumbnail = flatten(thumbnail, options.background_rgb.unwrap_or([255, 255, 255]));
+
+    let (data, quality) = match options.max_bytes {
+        None => (encode_jpeg(&thumbnail, grayscale, quality)?, quality),
+        Some(max_bytes) => loop {
+            if let Some(fit) = fit_jpeg(&thumbnail, grayscale, quality, max_bytes as usize)? {
+                break fit;
+            }
+            if thumbnail.width() == 1 && thumbnail.height() == 1 {
+                return Err(JsError::new(&format!(
+                    "Thumbnail cannot fit within {} bytes",
+                    max_bytes
+                )));
+            }
+            let shrink = |side: u32| ((side as f32 * BUDGET_SHRINK) as u32).max(1);
+            thumbnail = thumbnail.thumbnail_exact(shrink(thumbnail.width()), shrink(thumbnail.height()));
+        },
+    };
+
+    Ok(ThumbnailResult {
+        width: thumbnail.width(),
+        height: thumbnail.height(),
+        input_bytes: buffer.len() as u32,
+        output_bytes: data.len() as u32,
+        data,
+        quality,
+    })
+}
+
+/// Binary-search the highest quality from `MIN_BUDGET_QUALITY` up to `max`
+/// whose output fits in `max_bytes`, or `None` when even the lowest does not.
+fn fit_jpeg(
+    img: &DynamicImage,
+    grayscale: bool,
+    max: u8,
+    max_bytes: usize,
+) -> Result<Option<(Vec<u8>, u8)>, JsError> {
+    let (mut lo, mut hi) = (MIN_BUDGET_QUALITY.min(max), max);
+    let mut best = None;
+    while lo <= hi {
+        let mid = lo + (hi - lo) / 2;
+        let data = encode_jpeg(img, grayscale, mid)?;
+        if data.len() <= max_bytes {
+            best = Some((data, mid));
+            lo = mid + 1;
+        } else {
+            hi = mid - 1;
+        }
+    }
+    Ok(best)
+}
+
+fn encode_jpeg(img: &DynamicImage, grayscale: bool, quality: u8) -> Result<Vec<u8>, JsError> {
+    // Convert to L8 or RGB8, the layouts supported by the JPEG encoder
+    let mut data = Vec::new();
+    let encoder = JpegEncoder::new_with_quality(&mut data, quality);
+    let encoded = if grayscale {
+        encoder.write_image(
+            img.to_luma8().as_raw(),
+            img.width(),
+            img.height(),
+            ExtendedColorType::L8,
+        )
+    } else {
+        encoder.write_image(
+            img.to_rgb8().as_raw(),
+            img.width(),
+            img.height(),
+            ExtendedColorType::Rgb8,
+        )
+    };
+    encoded.map_err(|e| JsError::new(&format!("Encode error: {}", e)))?;
+    Ok(data)
+}
+
+/// Composite images with alpha onto `background`; JPEG has no alpha and
+/// dropping it would leave transparent areas black.
+fn flatten(img: DynamicImage, background: [u8; 3]) -> DynamicImage {
+    if !img.color().has_alpha() {
+        return img;
+    }
+
+    let rgba = img.to_rgba8();
+    let rgb = image::RgbImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+        let p = rgba.get_pixel(x, y);
+        let alpha = p[3] as u32;
+        image::Rgb(std::array::from_fn(|c| {
+            ((p[c] as u32 * alpha + background[c] as u32 * (255 - alpha) + 127) / 255) as u8
+        }))
+    });
+    DynamicImage::ImageRgb8(rgb)
+}
+
+/// Downscale in linear light. Averaging sRGB-encoded values darkens fine
+/// high-contrast detail (text, star fields); decoding to linear first keeps
+/// the overall brightness of the source.
+fn thumbnail_linear(img: &DynamicImage, width: u32, height: u32) -> DynamicImage {
+    let mut linear = img.to_rgba32f();
+    for pixel in linear.pixels_mut() {
+        for c in 0..3 {
+            pixel[c] = srgb_to_linear(pixel[c]);
+        }
+    }
+
+    let small = imageops::thumbnail(&linear, width, height);
+    let srgb = RgbaImage::from_fn(small.width(), small.height(), |x, y| {
+        let p = small.get_pixel(x, y);
+        image::Rgba([
+            to_u8(linear_to_srgb(p[0])),
+            to_u8(linear_to_srgb(p[1])),
+            to_u8(linear_to_srgb(p[2])),
+            to_u8(p[3]),
+        ])
+    });
+    DynamicImage::ImageRgba8(srgb)
+}
+
+fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(c: f32) -> f32 {
+    let c = c.clamp(0.0, 1.0);
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn to_u8(c: f32) -> u8 {
+    (c.clamp(0.0, 1.0) * 255.0).round() as u8
 }
 
 fn calculate_size(orig_w: u32, orig_h: u32, max_size: u32) -> (u32, u32) {
@@ -52,4 +697,76 @@ fn calculate_size(orig_w: u32, orig_h: u32, max_size: u32) -> (u32, u32) {
     } else {
         ((max_size as f32 * ratio) as u32, max_size)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        let crc = crc32(&chunk[4..]);
+        chunk.extend_from_slice(&crc.to_be_bytes());
+        chunk
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in bytes {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg());
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn probe_rejects_oversized_headers() {
+        // A valid IHDR announcing 100000x100000 RGBA, with an empty IDAT.
+        let mut ihdr = Vec::new();
+        ihdr.extend_from_slice(&100_000u32.to_be_bytes());
+        ihdr.extend_from_slice(&100_000u32.to_be_bytes());
+        ihdr.extend_from_slice(&[8, 6, 0, 0, 0]);
+        let mut bomb = b"\x89PNG\r\n\x1a\n".to_vec();
+        bomb.extend(png_chunk(b"IHDR", &ihdr));
+        bomb.extend(png_chunk(b"IDAT", &[]));
+        bomb.extend(png_chunk(b"IEND", &[]));
+
+        let result = probe(&bomb);
+        assert!(!result.decodable);
+        assert!(result.error.unwrap().contains("pixel limit"));
+        assert!(hash_item(&bomb).is_none());
+        assert!(hash_item(&bomb[..12]).is_none());
+    }
+
+    #[test]
+    fn probe_reads_heif_dimensions_instead_of_trusting_ftyp() {
+        // A bare ftyp box: recognised as HEIF, but there is no image to size.
+        let heif = b"\0\0\0\x18ftypheic\0\0\0\0mif1heic";
+        let result = probe(heif);
+        assert_eq!(result.format.as_deref(), Some("heif"));
+        assert!(!result.decodable);
+        assert!(result.error.is_some());
+    }
+
+    #[test]
+    fn grouping_survives_a_panicking_item() {
+        let mut good = Vec::new();
+        DynamicImage::new_rgb8(8, 8)
+            .write_to(&mut Cursor::new(&mut good), image::ImageFormat::Png)
+            .unwrap();
+        let hashes: Vec<_> = (0..3)
+            .map(|index| {
+                if index == 1 {
+                    decode::tests::panic_on_next_decode();
+                }
+                hash_item(&good)
+            })
+            .collect();
+        assert!(hashes[0].is_some());
+        assert!(hashes[1].is_none());
+        assert_eq!(hashes[2], hashes[0]);
+    }
+}
@@ -0,0 +1,75 @@
+use image::{GrayImage, Luma};
+
+/// Share of the darkest and of the brightest pixels `auto_contrast` clips.
+const CLIP_FRACTION: f32 = 0.01;
+/// Adaptive threshold window, as a fraction of the longer side.
+const WINDOW_FRACTION: u32 = 16;
+/// How far below its window mean a pixel must be to count as ink.
+const THRESHOLD_PERCENT: u32 = 15;
+
+/// Stretch levels so the 1st and 99th percentiles map to black and white.
+pub fn auto_contrast(img: &mut GrayImage) {
+    let mut histogram = [0u64; 256];
+    for pixel in img.pixels() {
+        histogram[pixel[0] as usize] += 1;
+    }
+    let clip = (img.pixels().len() as f32 * CLIP_FRACTION) as u64;
+    let low = first_past(&histogram, clip, 0..256);
+    let high = first_past(&histogram, clip, (0..256).rev());
+    if high <= low {
+        return;
+    }
+
+    let range = (high - low) as f32;
+    let lut: [u8; 256] = std::array::from_fn(|level| {
+        ((level as f32 - low as f32) / range * 255.0).round().clamp(0.0, 255.0) as u8
+    });
+    for pixel in img.pixels_mut() {
+        pixel[0] = lut[pixel[0] as usize];
+    }
+}
+
+/// First level in `levels` order at which more than `clip` pixels have been
+/// passed.
+fn first_past(histogram: &[u64; 256], clip: u64, levels: impl Iterator<Item = usize>) -> usize {
+    let mut seen = 0;
+    for level in levels {
+        seen += histogram[level];
+        if seen > clip {
+            return level;
+        }
+    }
+    0
+}
+
+/// Bradley–Roth adaptive threshold: a pixel becomes black when it is more
+/// than 15% darker than the mean of the window around it, white otherwise.
+/// Unlike a global threshold this survives shadows and uneven lighting
+/// across a page. Window sums come from a summed-area table.
+pub fn adaptive_threshold(img: &GrayImage) -> GrayImage {
+    let (width, height) = img.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let half = (width.max(height) / WINDOW_FRACTION / 2).max(1) as usize;
+
+    let mut sums = vec![0u64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0u64;
+        for x in 0..w {
+            row += img.get_pixel(x as u32, y as u32)[0] as u64;
+            sums[(y + 1) * (w + 1) + x + 1] = sums[y * (w + 1) + x + 1] + row;
+        }
+    }
+
+    GrayImage::from_fn(width, height, |x, y| {
+        let (x, y) = (x as usize, y as usize);
+        let (x0, x1) = (x.saturating_sub(half), (x + half + 1).min(w));
+        let (y0, y1) = (y.saturating_sub(half), (y + half + 1).min(h));
+        let area = ((x1 - x0) * (y1 - y0)) as u64;
+        let sum = sums[y1 * (w + 1) + x1] + sums[y0 * (w + 1) + x0]
+            - sums[y0 * (w + 1) + x1]
+            - sums[y1 * (w + 1) + x0];
+        let value = img.get_pixel(x as u32, y as u32)[0] as u64;
+        let ink = value * area * 100 < sum * (100 - THRESHOLD_PERCENT as u64);
+        Luma([if ink { 0 } else { 255 }])
+    })
+}
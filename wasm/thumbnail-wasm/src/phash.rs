@@ -0,0 +1,167 @@
+use image::{imageops::FilterType, DynamicImage};
+use serde::Serialize;
+
+/// Edge length of the grayscale square every image is normalized to.
+pub const NORMALIZED_SIZE: u32 = 32;
+/// Edge length of the low-frequency DCT block that becomes the hash.
+pub const HASH_SIZE: usize = 8;
+/// Resampling filter used for normalization. Fixed so the hash does not
+/// depend on caller settings.
+pub const NORMALIZE_FILTER: FilterType = FilterType::Triangle;
+
+/// Normalization applied before hashing, exposed so callers can check that
+/// stored hashes were produced with the same parameters.
+#[derive(Serialize)]
+pub struct Normalization {
+    pub size: u32,
+    pub hash_size: u32,
+    pub filter: &'static str,
+    pub color: &'static str,
+}
+
+pub fn normalization() -> Normalization {
+    Normalization {
+        size: NORMALIZED_SIZE,
+        hash_size: HASH_SIZE as u32,
+        filter: "triangle",
+        color: "luma8",
+    }
+}
+
+/// 64-bit DCT perceptual hash.
+///
+/// The image is reduced to 8-bit luma, resized to a fixed 32×32 square with a
+/// fixed filter, transformed with a 2D DCT, and each of the 8×8 lowest
+/// frequencies is compared against their median. Only decoded pixels feed the
+/// hash, so a lossless re-encode of the same pixels hashes identically.
+pub fn phash(img: &DynamicImage) -> u64 {
+    let gray = img.to_luma8();
+    let normalized =
+        image::imageops::resize(&gray, NORMALIZED_SIZE, NORMALIZED_SIZE, NORMALIZE_FILTER);
+
+    let n = NORMALIZED_SIZE as usize;
+    let pixels: Vec<f64> = normalized.as_raw().iter().map(|&v| v as f64).collect();
+    let coefficients = dct_2d(&pixels, n);
+
+    let mut low = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+    for y in 0..HASH_SIZE {
+        for x in 0..HASH_SIZE {
+            low.push(coefficients[y * n + x]);
+        }
+    }
+
+    // The DC term only encodes average brightness; leave it out of the median.
+    let mut sorted: Vec<f64> = low[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    low.iter()
+        .enumerate()
+        .fold(0u64, |hash, (i, &c)| if c > median { hash | (1 << (63 - i)) } else { hash })
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Single-linkage groups of `hashes` whose Hamming distance is at most
+/// `max_distance`, as index lists of two or more members. Every pair is
+/// compared, so the cost grows with the square of the batch size.
+pub fn group(hashes: &[(usize, u64)], max_distance: u32) -> Vec<Vec<usize>> {
+    // Union-find over positions in `hashes`.
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if hamming_distance(hashes[i].1, hashes[j].1) <= max_distance {
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    let mut groups: Vec<Vec<usize>> = vec![Vec::new(); hashes.len()];
+    for (i, &(index, _)) in hashes.iter().enumerate() {
+        let r = root(&mut parent, i);
+        groups[r].push(index);
+    }
+    groups.retain(|g| g.len() > 1);
+    groups
+}
+
+/// Separable, unnormalized DCT-II over an `n`×`n` block.
+fn dct_2d(input: &[f64], n: usize) -> Vec<f64> {
+    let mut cosines = vec![0.0; n * n];
+    for k in 0..n {
+        for i in 0..n {
+            cosines[k * n + i] =
+                (std::f64::consts::PI / n as f64 * (i as f64 + 0.5) * k as f64).cos();
+        }
+    }
+
+    let mut rows = vec![0.0; n * n];
+    for y in 0..n {
+        for k in 0..n {
+            rows[y * n + k] = (0..n).map(|i| input[y * n + i] * cosines[k * n + i]).sum();
+        }
+    }
+
+    let mut out = vec![0.0; n * n];
+    for x in 0..n {
+        for k in 0..n {
+            out[k * n + x] = (0..n).map(|i| rows[i * n + x] * cosines[k * n + i]).sum();
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn encode(img: &DynamicImage, format: ImageFormat) -> Vec<u8> {
+        let mut out = Vec::new();
+        img.write_to(&mut Cursor::new(&mut out), format).unwrap();
+        out
+    }
+
+    #[test]
+    fn jpeg_and_its_png_reencode_hash_the_same() {
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(120, 80, |x, y| {
+            let disc = (x as i32 - 40).pow(2) + (y as i32 - 40).pow(2) < 600;
+            if disc {
+                image::Rgb([230, 40, 40])
+            } else {
+                image::Rgb([(x * 2) as u8, (y * 3) as u8, 90])
+            }
+        }));
+        let jpeg = encode(&img, ImageFormat::Jpeg);
+        let from_jpeg = crate::decode::decode_image(&jpeg).unwrap();
+        let png = encode(&from_jpeg, ImageFormat::Png);
+        let from_png = crate::decode::decode_image(&png).unwrap();
+
+        assert_eq!(phash(&from_jpeg), phash(&from_png));
+        // The lossy JPEG itself stays close to the original.
+        assert!(hamming_distance(phash(&img), phash(&from_jpeg)) <= 4);
+    }
+
+    #[test]
+    fn normalization_is_pinned() {
+        // Changing any of these invalidates every stored hash.
+        let n = normalization();
+        assert_eq!(n.size, 32);
+        assert_eq!(n.hash_size, 8);
+        assert_eq!(n.filter, "triangle");
+        assert_eq!(n.color, "luma8");
+        assert_eq!(NORMALIZE_FILTER, FilterType::Triangle);
+    }
+}
@@ -0,0 +1,81 @@
+use image::DynamicImage;
+
+/// Longest edge of the copy saliency is measured on.
+pub const SALIENCY_SIZE: u32 = 128;
+/// Weight left at the corners by the center bias; the center weighs 1.0.
+const CORNER_WEIGHT: f64 = 0.5;
+
+/// Edge-density saliency of a downscaled copy, stored as a summed-area table
+/// so any window can be scored in constant time.
+pub struct SaliencyMap {
+    pub width: u32,
+    pub height: u32,
+    integral: Vec<f64>,
+}
+
+impl SaliencyMap {
+    /// Luma gradient magnitude per pixel, scaled by a center bias that falls
+    /// off quadratically to `CORNER_WEIGHT`, so an otherwise flat image
+    /// favors a centered crop.
+    pub fn new(img: &DynamicImage) -> SaliencyMap {
+        let gray = img.thumbnail(SALIENCY_SIZE, SALIENCY_SIZE).to_luma8();
+        let (width, height) = gray.dimensions();
+        let at = |x: i64, y: i64| {
+            let x = x.clamp(0, width as i64 - 1) as u32;
+            let y = y.clamp(0, height as i64 - 1) as u32;
+            gray.get_pixel(x, y)[0] as f64
+        };
+
+        let stride = width as usize + 1;
+        let mut integral = vec![0.0; stride * (height as usize + 1)];
+        for y in 0..height {
+            let mut row = 0.0;
+            for x in 0..width {
+                let (xi, yi) = (x as i64, y as i64);
+                let gradient =
+                    (at(xi + 1, yi) - at(xi - 1, yi)).abs() + (at(xi, yi + 1) - at(xi, yi - 1)).abs();
+                let dx = (x as f64 + 0.5) / width as f64 * 2.0 - 1.0;
+                let dy = (y as f64 + 0.5) / height as f64 * 2.0 - 1.0;
+                let bias = 1.0 - (1.0 - CORNER_WEIGHT) * (dx * dx + dy * dy) / 2.0;
+
+                row += gradient * bias;
+                let i = (y as usize + 1) * stride + x as usize + 1;
+                integral[i] = integral[i - stride] + row;
+            }
+        }
+
+        SaliencyMap {
+            width,
+            height,
+            integral,
+        }
+    }
+
+    fn sum(&self, x: u32, y: u32, w: u32, h: u32) -> f64 {
+        let stride = self.width as usize + 1;
+        let (x0, y0) = (x as usize, y as usize);
+        let (x1, y1) = (x0 + w as usize, y0 + h as usize);
+        self.integral[y1 * stride + x1] - self.integral[y0 * stride + x1]
+            - self.integral[y1 * stride + x0]
+            + self.integral[y0 * stride + x0]
+    }
+
+    /// Top-left corner of the `w`×`h` window with the most saliency. Ties go
+    /// to the window closest to the center.
+    pub fn best_window(&self, w: u32, h: u32) -> (u32, u32) {
+        let (w, h) = (w.min(self.width), h.min(self.height));
+        let (cx, cy) = ((self.width - w) / 2, (self.height - h) / 2);
+        let mut best = (cx, cy);
+        let mut best_score = self.sum(cx, cy, w, h);
+        for y in 0..=self.height - h {
+            for x in 0..=self.width - w {
+                let score = self.sum(x, y, w, h);
+                if score > best_score {
+                    best = (x, y);
+                    best_score = score;
+                }
+            }
+        }
+        best
+    }
+}